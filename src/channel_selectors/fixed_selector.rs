@@ -0,0 +1,19 @@
+use crate::channel_selectors::{ChannelSelector, SelectionContext};
+
+pub struct FixedChannelSelector {
+    channel_name: String,
+}
+
+impl FixedChannelSelector {
+    pub fn new(channel_name: String) -> Self {
+        Self { channel_name }
+    }
+}
+
+impl ChannelSelector for FixedChannelSelector {
+    fn select(&mut self, context: &SelectionContext) -> usize {
+        context
+            .position(&self.channel_name)
+            .unwrap_or(context.current_index)
+    }
+}
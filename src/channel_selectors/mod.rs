@@ -0,0 +1,67 @@
+pub(crate) mod fixed_selector;
+pub(crate) mod motion_priority_selector;
+pub(crate) mod round_robin_selector;
+pub(crate) mod schedule_selector;
+
+use crate::config::{OmniPaneConfig, SwitchingMode};
+use crate::video_display::motion_detection::MotionState;
+use crate::video_display::video_channel::{ChannelState, VideoChannel};
+pub use fixed_selector::FixedChannelSelector;
+pub use motion_priority_selector::MotionPriorityChannelSelector;
+pub use round_robin_selector::RoundRobinChannelSelector;
+pub use schedule_selector::ScheduleChannelSelector;
+
+/// Snapshot of a channel, taken when choosing the channel to display.
+pub struct ChannelInfo {
+    pub name: String,
+    pub state: ChannelState,
+    pub motion: MotionState,
+}
+
+impl ChannelInfo {
+    pub(crate) fn from_channel(channel: &VideoChannel) -> Self {
+        Self {
+            name: channel.name.clone(),
+            state: channel.state(),
+            motion: channel.motion(),
+        }
+    }
+}
+
+pub struct SelectionContext<'a> {
+    pub channels: &'a [ChannelInfo],
+    /// Index of the channel currently displayed.
+    pub current_index: usize,
+}
+
+impl SelectionContext<'_> {
+    pub fn position(&self, channel_name: &str) -> Option<usize> {
+        self.channels
+            .iter()
+            .position(|channel| channel.name == channel_name)
+    }
+}
+
+pub trait ChannelSelector {
+    /// Returns the index of the channel to display. Called for every displayed frame,
+    /// so it should not block.
+    fn select(&mut self, context: &SelectionContext) -> usize;
+}
+
+pub fn create_selector(config: &OmniPaneConfig) -> Box<dyn ChannelSelector> {
+    let switching = &config.switching;
+    match switching.mode {
+        SwitchingMode::RoundRobin => Box::new(RoundRobinChannelSelector::new(config)),
+        SwitchingMode::Fixed => Box::new(FixedChannelSelector::new(
+            switching.channel.clone().unwrap_or_default(),
+        )),
+        SwitchingMode::Schedule => Box::new(ScheduleChannelSelector::new(
+            switching.schedule.clone(),
+            RoundRobinChannelSelector::new(config),
+        )),
+        SwitchingMode::MotionPriority => Box::new(MotionPriorityChannelSelector::new(
+            switching.min_dwell(),
+            RoundRobinChannelSelector::new(config),
+        )),
+    }
+}
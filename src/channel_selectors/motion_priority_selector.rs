@@ -0,0 +1,50 @@
+use crate::channel_selectors::{ChannelSelector, RoundRobinChannelSelector, SelectionContext};
+use crate::video_display::video_channel::ChannelState;
+use std::time::{Duration, Instant};
+
+/// Shows the channel with the largest moving area, staying on each channel for at
+/// least the minimum dwell time. Falls back to round-robin when nothing is moving.
+pub struct MotionPriorityChannelSelector {
+    min_dwell: Duration,
+    fallback: RoundRobinChannelSelector,
+    last_index: Option<usize>,
+    last_switch: Instant,
+}
+
+impl MotionPriorityChannelSelector {
+    pub fn new(min_dwell: Duration, fallback: RoundRobinChannelSelector) -> Self {
+        Self {
+            min_dwell,
+            fallback,
+            last_index: None,
+            last_switch: Instant::now(),
+        }
+    }
+}
+
+impl ChannelSelector for MotionPriorityChannelSelector {
+    fn select(&mut self, context: &SelectionContext) -> usize {
+        if self.last_index != Some(context.current_index) {
+            self.last_index = Some(context.current_index);
+            self.last_switch = Instant::now();
+        }
+
+        if self.last_switch.elapsed() < self.min_dwell {
+            return context.current_index;
+        }
+
+        let most_active_channel = context
+            .channels
+            .iter()
+            .enumerate()
+            .filter(|(_, channel)| {
+                channel.state == ChannelState::Connected && channel.motion.has_motion()
+            })
+            .max_by(|(_, a), (_, b)| a.motion.moving_area.total_cmp(&b.motion.moving_area));
+
+        match most_active_channel {
+            Some((index, _)) => index,
+            None => self.fallback.select(context),
+        }
+    }
+}
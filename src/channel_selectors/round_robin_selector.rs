@@ -0,0 +1,64 @@
+use crate::channel_selectors::{ChannelSelector, SelectionContext};
+use crate::config::OmniPaneConfig;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+pub struct RoundRobinChannelSelector {
+    default_dwell: Duration,
+    channel_dwell: HashMap<String, Duration>,
+    last_index: Option<usize>,
+    last_switch: Instant,
+}
+
+impl RoundRobinChannelSelector {
+    pub fn new(config: &OmniPaneConfig) -> Self {
+        let channel_dwell = config
+            .channels
+            .iter()
+            .filter_map(|channel| {
+                channel
+                    .dwell_secs
+                    .map(|dwell| (channel.name.clone(), Duration::from_secs(dwell)))
+            })
+            .collect();
+
+        Self {
+            default_dwell: config.switching.interval(),
+            channel_dwell,
+            last_index: None,
+            last_switch: Instant::now(),
+        }
+    }
+
+    fn get_dwell(&self, channel_name: &str) -> Duration {
+        self.channel_dwell
+            .get(channel_name)
+            .copied()
+            .unwrap_or(self.default_dwell)
+    }
+}
+
+impl ChannelSelector for RoundRobinChannelSelector {
+    fn select(&mut self, context: &SelectionContext) -> usize {
+        let n_channels = context.channels.len();
+        if n_channels == 0 {
+            return 0;
+        }
+
+        // The channel was changed by someone else, give it its full dwell time
+        if self.last_index != Some(context.current_index) {
+            self.last_index = Some(context.current_index);
+            self.last_switch = Instant::now();
+        }
+
+        let current_channel = &context.channels[context.current_index];
+        if self.last_switch.elapsed() < self.get_dwell(&current_channel.name) {
+            return context.current_index;
+        }
+
+        let next_index = (context.current_index + 1) % n_channels;
+        self.last_index = Some(next_index);
+        self.last_switch = Instant::now();
+        next_index
+    }
+}
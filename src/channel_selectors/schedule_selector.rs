@@ -0,0 +1,32 @@
+use crate::channel_selectors::{ChannelSelector, RoundRobinChannelSelector, SelectionContext};
+use crate::config::ScheduleEntry;
+use chrono::Local;
+
+pub struct ScheduleChannelSelector {
+    schedule: Vec<ScheduleEntry>,
+    fallback: RoundRobinChannelSelector,
+}
+
+impl ScheduleChannelSelector {
+    pub fn new(schedule: Vec<ScheduleEntry>, fallback: RoundRobinChannelSelector) -> Self {
+        Self { schedule, fallback }
+    }
+}
+
+impl ChannelSelector for ScheduleChannelSelector {
+    fn select(&mut self, context: &SelectionContext) -> usize {
+        let now = Local::now().time();
+
+        // The first matching entry wins when periods overlap
+        let scheduled_index = self
+            .schedule
+            .iter()
+            .find(|entry| entry.contains(now))
+            .and_then(|entry| context.position(&entry.channel));
+
+        match scheduled_index {
+            Some(index) => index,
+            None => self.fallback.select(context),
+        }
+    }
+}
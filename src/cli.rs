@@ -0,0 +1,122 @@
+use crate::config::{
+    self, overlay_config, ChannelConfig, ConfigResult, OmniPaneConfig, OverlayConfig,
+    OverlayStyle, TemperatureUnit, TimeZoneConfig,
+};
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(name = "omni_pane", version, about = "Displays and monitors video streams")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Display the configured video streams
+    Run(RunArgs),
+    /// Open a video stream and print its properties
+    Probe {
+        /// URL, file path or device of the stream
+        url: String,
+    },
+    /// List the local V4L2 capture devices
+    ListDevices,
+    /// Check a configuration file without starting the display
+    Validate {
+        /// Path of the TOML configuration file
+        config: PathBuf,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// Path of the TOML configuration file
+    #[arg(long, short, conflicts_with_all = ["urls", "overlay_time", "overlay_temperature"])]
+    pub config: Option<PathBuf>,
+
+    /// URLs, file paths or devices of the streams to display
+    #[arg(required_unless_present = "config")]
+    pub urls: Vec<String>,
+
+    /// Show the current time overlay
+    #[arg(long)]
+    pub overlay_time: bool,
+
+    /// strftime-like format of the time overlay
+    #[arg(long, requires = "overlay_time", default_value = overlay_config::DEFAULT_TIME_FORMAT)]
+    pub time_format: String,
+
+    /// Timezone of the time overlay: "local" or an IANA name like "Europe/Berlin"
+    #[arg(long, requires = "overlay_time", default_value = "UTC", value_parser = parse_timezone)]
+    pub time_zone: TimeZoneConfig,
+
+    /// Text written before the time
+    #[arg(long, requires = "overlay_time")]
+    pub time_label: Option<String>,
+
+    /// Show the temperature of the given 1-Wire sensor, can be repeated
+    #[arg(long, value_name = "SENSOR_ID")]
+    pub overlay_temperature: Vec<String>,
+
+    /// Unit of the temperature overlays
+    #[arg(long, default_value = "celsius", value_parser = parse_temperature_unit)]
+    pub temperature_unit: TemperatureUnit,
+}
+
+impl RunArgs {
+    /// Builds the configuration either from the config file or from the command line
+    /// arguments. The config file path is returned so it can be watched for changes.
+    pub fn load_config(&self) -> ConfigResult<(OmniPaneConfig, Option<PathBuf>)> {
+        if let Some(config_path) = &self.config {
+            let config = config::load_config(config_path)?;
+            return Ok((config, Some(config_path.clone())));
+        }
+
+        let channels = self
+            .urls
+            .iter()
+            .cloned()
+            .map(ChannelConfig::from_url)
+            .collect();
+
+        let mut overlays = Vec::new();
+        if self.overlay_time {
+            overlays.push(OverlayConfig::Time {
+                format: self.time_format.clone(),
+                timezone: self.time_zone,
+                label: self.time_label.clone(),
+                style: OverlayStyle::default(),
+            });
+        }
+        for sensor_id in &self.overlay_temperature {
+            overlays.push(OverlayConfig::Temperature {
+                sensor_id: Some(sensor_id.clone()),
+                unit: self.temperature_unit,
+                label: None,
+                offset: 0.0,
+                sysfs_root: overlay_config::default_w1_sysfs_root(),
+                max_age_secs: overlay_config::default_max_age_secs(),
+                style: OverlayStyle::default(),
+            });
+        }
+
+        let config = OmniPaneConfig::new(channels, overlays);
+        config.validate()?;
+        Ok((config, None))
+    }
+}
+
+fn parse_timezone(name: &str) -> Result<TimeZoneConfig, String> {
+    TimeZoneConfig::try_from(name.to_string())
+}
+
+fn parse_temperature_unit(name: &str) -> Result<TemperatureUnit, String> {
+    match name {
+        "celsius" => Ok(TemperatureUnit::Celsius),
+        "fahrenheit" => Ok(TemperatureUnit::Fahrenheit),
+        "kelvin" => Ok(TemperatureUnit::Kelvin),
+        _ => Err("expected \"celsius\", \"fahrenheit\" or \"kelvin\"".to_string()),
+    }
+}
@@ -0,0 +1,79 @@
+use crate::config::{load_config, OmniPaneConfig};
+use crate::core::shutdown;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Watches the config file for changes and keeps the latest valid configuration
+/// until it is picked up with `take_update`.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    poll_interval: Duration,
+    pending_config: Arc<Mutex<Option<OmniPaneConfig>>>,
+    thread_handle: Option<JoinHandle<()>>,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: PathBuf, poll_interval: Duration) -> Self {
+        Self {
+            path,
+            poll_interval,
+            pending_config: Arc::new(Mutex::new(None)),
+            thread_handle: None,
+        }
+    }
+
+    pub fn start(&mut self, is_running: Arc<AtomicBool>) {
+        let path = self.path.clone();
+        let poll_interval = self.poll_interval;
+        let pending_config = self.pending_config.clone();
+
+        self.thread_handle = Some(thread::spawn(move || {
+            let mut last_modified = get_modified_time(&path);
+
+            while is_running.load(Ordering::Relaxed) {
+                shutdown::sleep_while_running(poll_interval, &is_running);
+
+                let modified = get_modified_time(&path);
+                if modified == last_modified {
+                    continue;
+                }
+                last_modified = modified;
+
+                match load_config(&path) {
+                    Ok(config) => {
+                        println!("Config file {} changed, reloading", path.display());
+                        if let Ok(mut pending_config) = pending_config.lock() {
+                            *pending_config = Some(config);
+                        }
+                    }
+                    Err(e) => {
+                        println!("Config reload ignored: {}", e);
+                    }
+                }
+            }
+        }));
+    }
+
+    pub fn join(&mut self, deadline: Instant) -> bool {
+        match self.thread_handle.take() {
+            Some(handle) => shutdown::join_until(handle, deadline, "config watcher"),
+            None => true,
+        }
+    }
+
+    pub fn take_update(&self) -> Option<OmniPaneConfig> {
+        match self.pending_config.lock() {
+            Ok(mut pending_config) => pending_config.take(),
+            Err(_) => None,
+        }
+    }
+}
+
+fn get_modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
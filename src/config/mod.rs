@@ -0,0 +1,66 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+pub(crate) mod config_watcher;
+pub(crate) mod omni_pane_config;
+pub(crate) mod overlay_config;
+pub(crate) mod recording_config;
+pub(crate) mod snapshot_config;
+
+pub use config_watcher::ConfigWatcher;
+pub use omni_pane_config::{
+    ChannelConfig, DisplayConfig, FrameAnnotation, KeysConfig, LayoutMode, OmniPaneConfig,
+    PictureInPictureConfig, ScheduleEntry, SwitchingMode,
+};
+pub use overlay_config::{
+    BackgroundConfig, OverlayConfig, OverlayFont, OverlayStyle, TemperatureUnit, TimeZoneConfig,
+};
+pub use recording_config::RecordingConfig;
+pub use snapshot_config::{ImageFormat, SnapshotConfig};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid { key: String, message: String },
+}
+
+impl ConfigError {
+    pub(crate) fn invalid(key: impl Into<String>, message: impl Into<String>) -> Self {
+        ConfigError::Invalid {
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => {
+                write!(f, "Could not read config file {}: {}", path.display(), e)
+            }
+            ConfigError::Parse(path, e) => {
+                write!(f, "Could not parse config file {}: {}", path.display(), e)
+            }
+            ConfigError::Invalid { key, message } => {
+                write!(f, "Invalid value for `{}`: {}", key, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+pub type ConfigResult<T> = Result<T, ConfigError>;
+
+pub fn load_config(path: &Path) -> ConfigResult<OmniPaneConfig> {
+    let content =
+        std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+    let mut config: OmniPaneConfig =
+        toml::from_str(&content).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))?;
+
+    config.fill_defaults();
+    config.validate()?;
+    Ok(config)
+}
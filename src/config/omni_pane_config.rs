@@ -0,0 +1,625 @@
+use crate::config::{ConfigError, ConfigResult, OverlayConfig, RecordingConfig, SnapshotConfig};
+use crate::video_display::image_manipulation::TextPosition;
+use crate::video_display::key_bindings;
+use crate::video_display::text_renderer::TrueTypeFont;
+use chrono::NaiveTime;
+use opencv::core::Size;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const DEFAULT_FPS: u32 = 50;
+const MAX_FPS: u32 = 1000;
+const DEFAULT_SWITCH_INTERVAL_SECS: u64 = 10;
+const DEFAULT_MIN_DWELL_SECS: u64 = 5;
+const DEFAULT_RECONNECT_INITIAL_DELAY_MS: u64 = 500;
+const DEFAULT_RECONNECT_MAX_DELAY_MS: u64 = 30_000;
+const DEFAULT_MAX_RECONNECT_ATTEMPTS: u32 = 10;
+const DEFAULT_DISPLAY_WIDTH: u32 = 1280;
+const DEFAULT_DISPLAY_HEIGHT: u32 = 720;
+const DEFAULT_MOTION_LAYOUT_HOLD_SECS: u64 = 5;
+const MAX_DISPLAY_DIMENSION: u32 = 8192;
+const DEFAULT_INSET_SIZE_PERCENT: u32 = 25;
+const MAX_INSET_SIZE_PERCENT: u32 = 90;
+const DEFAULT_INSET_MARGIN: u32 = 20;
+const DEFAULT_INSET_BORDER_WIDTH: u32 = 2;
+const DEFAULT_INSET_BORDER_COLOR: [u8; 3] = [255, 255, 255];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OmniPaneConfig {
+    pub channels: Vec<ChannelConfig>,
+    #[serde(default)]
+    pub overlays: Vec<OverlayConfig>,
+    #[serde(default)]
+    pub switching: SwitchingConfig,
+    #[serde(default)]
+    pub keys: KeysConfig,
+    #[serde(default)]
+    pub display: DisplayConfig,
+    #[serde(default)]
+    pub recording: RecordingConfig,
+    #[serde(default)]
+    pub snapshots: SnapshotConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelConfig {
+    pub url: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default = "default_fps")]
+    pub fps: u32,
+    /// How often the current frame is checked for movement. Defaults to every 5 frames.
+    pub motion_check_interval_ms: Option<u64>,
+    /// How old the background frame used for the movement comparison is. Defaults to 10 frames.
+    pub motion_comparison_interval_ms: Option<u64>,
+    /// Delay before the first attempt to reopen a lost stream. Doubles on every failure.
+    #[serde(default = "default_reconnect_initial_delay_ms")]
+    pub reconnect_initial_delay_ms: u64,
+    #[serde(default = "default_reconnect_max_delay_ms")]
+    pub reconnect_max_delay_ms: u64,
+    /// Failed attempts after which the channel is reported as failed.
+    #[serde(default = "default_max_reconnect_attempts")]
+    pub max_reconnect_attempts: u32,
+    /// How long round-robin switching stays on this channel. Defaults to
+    /// `switching.interval_secs`.
+    pub dwell_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SwitchingMode {
+    /// Shows every channel in turn for its dwell time.
+    #[default]
+    RoundRobin,
+    /// Always shows the channel named by `channel`.
+    Fixed,
+    /// Shows the channel scheduled for the current time of day, falling back to
+    /// round-robin outside of the scheduled periods.
+    Schedule,
+    /// Shows the channel with the largest moving area, falling back to round-robin
+    /// when nothing is moving.
+    MotionPriority,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SwitchingConfig {
+    #[serde(default)]
+    pub mode: SwitchingMode,
+    #[serde(default = "default_switch_interval_secs")]
+    pub interval_secs: u64,
+    /// Minimum time a channel stays displayed before motion can switch to another one.
+    #[serde(default = "default_min_dwell_secs")]
+    pub min_dwell_secs: u64,
+    /// Channel shown by the fixed mode.
+    pub channel: Option<String>,
+    #[serde(default)]
+    pub schedule: Vec<ScheduleEntry>,
+}
+
+/// What is drawn over the frames of an output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameAnnotation {
+    /// The frames as read from the camera.
+    Raw,
+    /// The frames with boxes around the moving regions.
+    MotionBoxes,
+    /// The frames with the motion boxes and the text of the overlays.
+    Composited,
+}
+
+impl FrameAnnotation {
+    pub fn draws_motion_boxes(&self) -> bool {
+        *self != FrameAnnotation::Raw
+    }
+
+    pub fn draws_overlays(&self) -> bool {
+        *self == FrameAnnotation::Composited
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum LayoutMode {
+    /// Shows the current channel alone, at the size of its frames.
+    #[default]
+    #[serde(rename = "1x1")]
+    Single,
+    /// Shows the channels in pages of 4 tiles, starting with the page of the current one.
+    #[serde(rename = "2x2")]
+    Grid2x2,
+    /// Shows the channels in pages of 9 tiles, starting with the page of the current one.
+    #[serde(rename = "3x3")]
+    Grid3x3,
+    /// Shows the current channel in a large tile and up to 7 others in small tiles
+    /// along the right and bottom edges.
+    #[serde(rename = "large_and_small")]
+    LargeAndSmall,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayConfig {
+    /// Size of the composed image of the tiled layouts and of the "no signal" slate.
+    pub width: u32,
+    pub height: u32,
+    pub layout: LayoutMode,
+    /// Layout used while the current channel has motion, e.g. "1x1" to show it full screen.
+    pub motion_layout: Option<LayoutMode>,
+    /// Time the motion layout is kept after the last motion, so it doesn't flicker
+    /// while moving regions come and go.
+    pub motion_layout_hold_secs: u64,
+    /// Inset of a second channel drawn over the 1x1 layout.
+    pub picture_in_picture: Option<PictureInPictureConfig>,
+    /// What is drawn over the displayed channels. The paused and frozen markers and the
+    /// disk alert are shown on the display whatever it is.
+    pub annotation: FrameAnnotation,
+    /// TrueType or OpenType font of the channel labels and of the "no signal" slate,
+    /// which can draw any character it contains. Defaults to the built-in font, which
+    /// can only draw ASCII text.
+    pub font_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PictureInPictureConfig {
+    /// Channel shown in the inset. Defaults to the channel with the most recent motion.
+    /// Nothing is shown while it is the current channel.
+    pub channel: Option<String>,
+    #[serde(default = "default_inset_corner")]
+    pub corner: TextPosition,
+    /// Width of the inset, in percent of the display width.
+    #[serde(default = "default_inset_size_percent")]
+    pub size_percent: u32,
+    /// Distance between the inset and the edges of the display, in pixels.
+    #[serde(default = "default_inset_margin")]
+    pub margin: u32,
+    #[serde(default = "default_inset_border_width")]
+    pub border_width: u32,
+    /// Border colour as [red, green, blue].
+    #[serde(default = "default_inset_border_color")]
+    pub border_color: [u8; 3],
+}
+
+/// Keys of the display window. Every action takes a list of key names: a single
+/// character, "space", "escape", "enter", "tab", an arrow ("left", "right", "up",
+/// "down") or a raw key code.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeysConfig {
+    pub quit: Vec<String>,
+    pub next_channel: Vec<String>,
+    pub previous_channel: Vec<String>,
+    pub toggle_pause: Vec<String>,
+    pub toggle_freeze: Vec<String>,
+    pub snapshot: Vec<String>,
+    /// Keys showing the channels, in the order of the channel list.
+    pub channels: Vec<String>,
+}
+
+/// Period of the day, in local time, in which a channel is shown by the schedule mode.
+/// Periods ending before they start wrap around midnight.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleEntry {
+    #[serde(deserialize_with = "deserialize_time")]
+    pub start: NaiveTime,
+    #[serde(deserialize_with = "deserialize_time")]
+    pub end: NaiveTime,
+    pub channel: String,
+}
+
+fn deserialize_time<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+    let time = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&time, "%H:%M")
+        .map_err(|_| serde::de::Error::custom(format!("invalid time \"{}\", expected HH:MM", time)))
+}
+
+fn default_inset_corner() -> TextPosition {
+    TextPosition::TopRight
+}
+
+fn default_inset_size_percent() -> u32 {
+    DEFAULT_INSET_SIZE_PERCENT
+}
+
+fn default_inset_margin() -> u32 {
+    DEFAULT_INSET_MARGIN
+}
+
+fn default_inset_border_width() -> u32 {
+    DEFAULT_INSET_BORDER_WIDTH
+}
+
+fn default_inset_border_color() -> [u8; 3] {
+    DEFAULT_INSET_BORDER_COLOR
+}
+
+fn default_fps() -> u32 {
+    DEFAULT_FPS
+}
+
+fn default_switch_interval_secs() -> u64 {
+    DEFAULT_SWITCH_INTERVAL_SECS
+}
+
+fn default_min_dwell_secs() -> u64 {
+    DEFAULT_MIN_DWELL_SECS
+}
+
+fn default_reconnect_initial_delay_ms() -> u64 {
+    DEFAULT_RECONNECT_INITIAL_DELAY_MS
+}
+
+fn default_reconnect_max_delay_ms() -> u64 {
+    DEFAULT_RECONNECT_MAX_DELAY_MS
+}
+
+fn default_max_reconnect_attempts() -> u32 {
+    DEFAULT_MAX_RECONNECT_ATTEMPTS
+}
+
+impl Default for SwitchingConfig {
+    fn default() -> Self {
+        Self {
+            mode: SwitchingMode::default(),
+            interval_secs: DEFAULT_SWITCH_INTERVAL_SECS,
+            min_dwell_secs: DEFAULT_MIN_DWELL_SECS,
+            channel: None,
+            schedule: Vec::new(),
+        }
+    }
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            width: DEFAULT_DISPLAY_WIDTH,
+            height: DEFAULT_DISPLAY_HEIGHT,
+            layout: LayoutMode::default(),
+            motion_layout: None,
+            motion_layout_hold_secs: DEFAULT_MOTION_LAYOUT_HOLD_SECS,
+            picture_in_picture: None,
+            annotation: FrameAnnotation::Composited,
+            font_file: None,
+        }
+    }
+}
+
+impl DisplayConfig {
+    pub fn size(&self) -> Size {
+        Size::new(self.width as i32, self.height as i32)
+    }
+
+    pub fn motion_layout_hold(&self) -> Duration {
+        Duration::from_secs(self.motion_layout_hold_secs)
+    }
+
+    fn validate(&self, channel_names: &HashSet<&str>) -> ConfigResult<()> {
+        for (key, value) in [
+            ("display.width", self.width),
+            ("display.height", self.height),
+        ] {
+            if value == 0 || value > MAX_DISPLAY_DIMENSION {
+                return Err(ConfigError::invalid(
+                    key,
+                    format!(
+                        "must be between 1 and {}, got {}",
+                        MAX_DISPLAY_DIMENSION, value
+                    ),
+                ));
+            }
+        }
+
+        if let Some(inset) = &self.picture_in_picture {
+            inset.validate(channel_names)?;
+        }
+        if let Some(font_file) = &self.font_file {
+            if let Err(e) = TrueTypeFont::load(font_file) {
+                return Err(ConfigError::invalid("display.font_file", e.to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PictureInPictureConfig {
+    fn validate(&self, channel_names: &HashSet<&str>) -> ConfigResult<()> {
+        if let Some(channel) = &self.channel {
+            if !channel_names.contains(channel.as_str()) {
+                return Err(ConfigError::invalid(
+                    "display.picture_in_picture.channel",
+                    format!("unknown channel \"{}\"", channel),
+                ));
+            }
+        }
+        /*
+        Only the text in the corners is moved clear of the inset, so an inset in the
+        centre would cover the centred overlays.
+        */
+        if self.corner == TextPosition::Center {
+            return Err(ConfigError::invalid(
+                "display.picture_in_picture.corner",
+                "must be \"top_left\", \"top_right\", \"bottom_left\" or \"bottom_right\"",
+            ));
+        }
+        if self.size_percent == 0 || self.size_percent > MAX_INSET_SIZE_PERCENT {
+            return Err(ConfigError::invalid(
+                "display.picture_in_picture.size_percent",
+                format!(
+                    "must be between 1 and {}, got {}",
+                    MAX_INSET_SIZE_PERCENT, self.size_percent
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for KeysConfig {
+    fn default() -> Self {
+        let keys = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        Self {
+            quit: keys(&["q", "escape"]),
+            next_channel: keys(&["right", "down"]),
+            previous_channel: keys(&["left", "up"]),
+            toggle_pause: keys(&["space"]),
+            toggle_freeze: keys(&["f"]),
+            snapshot: keys(&["s"]),
+            channels: keys(&["1", "2", "3", "4", "5", "6", "7", "8", "9", "0"]),
+        }
+    }
+}
+
+impl KeysConfig {
+    fn validate(&self) -> ConfigResult<()> {
+        let actions = [
+            ("quit", &self.quit),
+            ("next_channel", &self.next_channel),
+            ("previous_channel", &self.previous_channel),
+            ("toggle_pause", &self.toggle_pause),
+            ("toggle_freeze", &self.toggle_freeze),
+            ("snapshot", &self.snapshot),
+            ("channels", &self.channels),
+        ];
+
+        let mut used_keys = HashSet::new();
+        for (action, keys) in actions {
+            for (index, key) in keys.iter().enumerate() {
+                let key_path = format!("keys.{}[{}]", action, index);
+                let Some(code) = key_bindings::parse_key(key) else {
+                    return Err(ConfigError::invalid(
+                        key_path,
+                        format!("unknown key \"{}\"", key),
+                    ));
+                };
+                if !used_keys.insert(code) {
+                    return Err(ConfigError::invalid(
+                        key_path,
+                        format!("key \"{}\" is bound more than once", key),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl SwitchingConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+
+    pub fn min_dwell(&self) -> Duration {
+        Duration::from_secs(self.min_dwell_secs)
+    }
+
+    fn validate(&self, channel_names: &HashSet<&str>) -> ConfigResult<()> {
+        if self.interval_secs == 0 {
+            return Err(ConfigError::invalid(
+                "switching.interval_secs",
+                "must be greater than 0",
+            ));
+        }
+
+        if self.mode == SwitchingMode::Fixed {
+            match &self.channel {
+                None => {
+                    return Err(ConfigError::invalid(
+                        "switching.channel",
+                        "is required by the fixed mode",
+                    ));
+                }
+                Some(channel) if !channel_names.contains(channel.as_str()) => {
+                    return Err(ConfigError::invalid(
+                        "switching.channel",
+                        format!("unknown channel \"{}\"", channel),
+                    ));
+                }
+                Some(_) => {}
+            }
+        }
+
+        if self.mode == SwitchingMode::Schedule && self.schedule.is_empty() {
+            return Err(ConfigError::invalid(
+                "switching.schedule",
+                "at least one entry is required by the schedule mode",
+            ));
+        }
+        for (index, entry) in self.schedule.iter().enumerate() {
+            if !channel_names.contains(entry.channel.as_str()) {
+                return Err(ConfigError::invalid(
+                    format!("switching.schedule[{}].channel", index),
+                    format!("unknown channel \"{}\"", entry.channel),
+                ));
+            }
+            if entry.start == entry.end {
+                return Err(ConfigError::invalid(
+                    format!("switching.schedule[{}].end", index),
+                    "must be different from start",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ScheduleEntry {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl ChannelConfig {
+    pub fn from_url(url: String) -> Self {
+        Self {
+            url,
+            name: String::new(),
+            fps: DEFAULT_FPS,
+            motion_check_interval_ms: None,
+            motion_comparison_interval_ms: None,
+            reconnect_initial_delay_ms: DEFAULT_RECONNECT_INITIAL_DELAY_MS,
+            reconnect_max_delay_ms: DEFAULT_RECONNECT_MAX_DELAY_MS,
+            max_reconnect_attempts: DEFAULT_MAX_RECONNECT_ATTEMPTS,
+            dwell_secs: None,
+        }
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_millis(1000 / self.fps as u64)
+    }
+
+    pub fn motion_check_interval(&self) -> Duration {
+        match self.motion_check_interval_ms {
+            Some(interval) => Duration::from_millis(interval),
+            None => self.frame_duration() * 5,
+        }
+    }
+
+    pub fn motion_comparison_interval(&self) -> Duration {
+        match self.motion_comparison_interval_ms {
+            Some(interval) => Duration::from_millis(interval),
+            None => self.frame_duration() * 10,
+        }
+    }
+
+    fn validate(&self, key: &str) -> ConfigResult<()> {
+        if self.url.trim().is_empty() {
+            return Err(ConfigError::invalid(
+                format!("{}.url", key),
+                "must not be empty",
+            ));
+        }
+        if self.fps == 0 || self.fps > MAX_FPS {
+            return Err(ConfigError::invalid(
+                format!("{}.fps", key),
+                format!("must be between 1 and {}, got {}", MAX_FPS, self.fps),
+            ));
+        }
+        if self.motion_check_interval_ms == Some(0) {
+            return Err(ConfigError::invalid(
+                format!("{}.motion_check_interval_ms", key),
+                "must be greater than 0",
+            ));
+        }
+        if self.motion_comparison_interval_ms == Some(0) {
+            return Err(ConfigError::invalid(
+                format!("{}.motion_comparison_interval_ms", key),
+                "must be greater than 0",
+            ));
+        }
+        if self.reconnect_initial_delay_ms == 0 {
+            return Err(ConfigError::invalid(
+                format!("{}.reconnect_initial_delay_ms", key),
+                "must be greater than 0",
+            ));
+        }
+        if self.reconnect_max_delay_ms < self.reconnect_initial_delay_ms {
+            return Err(ConfigError::invalid(
+                format!("{}.reconnect_max_delay_ms", key),
+                "must not be smaller than reconnect_initial_delay_ms",
+            ));
+        }
+        if self.max_reconnect_attempts == 0 {
+            return Err(ConfigError::invalid(
+                format!("{}.max_reconnect_attempts", key),
+                "must be greater than 0",
+            ));
+        }
+        if self.dwell_secs == Some(0) {
+            return Err(ConfigError::invalid(
+                format!("{}.dwell_secs", key),
+                "must be greater than 0",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl OmniPaneConfig {
+    pub fn new(channels: Vec<ChannelConfig>, overlays: Vec<OverlayConfig>) -> Self {
+        let mut config = Self {
+            channels,
+            overlays,
+            switching: SwitchingConfig::default(),
+            keys: KeysConfig::default(),
+            display: DisplayConfig::default(),
+            recording: RecordingConfig::default(),
+            snapshots: SnapshotConfig::default(),
+        };
+        config.fill_defaults();
+        config
+    }
+
+    /// Gives every unnamed channel a name based on its position in the list.
+    pub(crate) fn fill_defaults(&mut self) {
+        for (index, channel) in self.channels.iter_mut().enumerate() {
+            if channel.name.trim().is_empty() {
+                channel.name = format!("Camera {}", index + 1);
+            }
+        }
+    }
+
+    pub fn validate(&self) -> ConfigResult<()> {
+        if self.channels.is_empty() {
+            return Err(ConfigError::invalid(
+                "channels",
+                "at least one channel is required",
+            ));
+        }
+
+        let mut names = HashSet::new();
+        for (index, channel) in self.channels.iter().enumerate() {
+            let key = format!("channels[{}]", index);
+            channel.validate(&key)?;
+            if !names.insert(channel.name.as_str()) {
+                return Err(ConfigError::invalid(
+                    format!("{}.name", key),
+                    format!("duplicated channel name \"{}\"", channel.name),
+                ));
+            }
+        }
+
+        for (index, overlay) in self.overlays.iter().enumerate() {
+            overlay.validate(&format!("overlays[{}]", index))?;
+        }
+
+        self.switching.validate(&names)?;
+        self.keys.validate()?;
+        self.display.validate(&names)?;
+        self.recording.validate(&names)?;
+        self.snapshots.validate(&names)?;
+
+        Ok(())
+    }
+}
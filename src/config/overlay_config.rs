@@ -0,0 +1,300 @@
+use crate::config::{ConfigError, ConfigResult};
+use crate::video_display::image_manipulation::TextPosition;
+use crate::video_display::text_renderer::TrueTypeFont;
+use chrono::format::{Item, StrftimeItems};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub(crate) const DEFAULT_TIME_FORMAT: &str = "%d/%m/%Y %H:%M:%S";
+const DEFAULT_W1_SYSFS_ROOT: &str = "/sys/bus/w1/devices";
+const DEFAULT_MAX_AGE_SECS: u64 = 30;
+const DEFAULT_STALE_COLOR: [u8; 3] = [255, 0, 0];
+const DEFAULT_TEXT_COLOR: [u8; 3] = [0, 255, 0];
+const DEFAULT_FONT_SCALE: f64 = 1.0;
+const DEFAULT_FONT_THICKNESS: u32 = 2;
+const DEFAULT_BACKGROUND_OPACITY: f64 = 0.5;
+const DEFAULT_BACKGROUND_PADDING: u32 = 6;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum OverlayConfig {
+    Time {
+        /// strftime-like format, see chrono's `format::strftime`.
+        #[serde(default = "default_time_format")]
+        format: String,
+        #[serde(default)]
+        timezone: TimeZoneConfig,
+        /// Text written before the time, e.g. "HQ".
+        label: Option<String>,
+        #[serde(default)]
+        style: OverlayStyle,
+    },
+    /// Temperature of 1-Wire sensors read from sysfs, one line per sensor.
+    Temperature {
+        /// Id of the sensor, like "28-000000000000". Every "28-*" sensor found under
+        /// `sysfs_root` is shown when it is not set.
+        sensor_id: Option<String>,
+        #[serde(default)]
+        unit: TemperatureUnit,
+        /// Text written before the temperature. Defaults to "Temperature", or to the
+        /// sensor id for discovered sensors.
+        label: Option<String>,
+        /// Calibration added to the readings, in degrees Celsius.
+        #[serde(default)]
+        offset: f64,
+        #[serde(default = "default_w1_sysfs_root")]
+        sysfs_root: PathBuf,
+        /// Age in seconds after which a reading is marked as stale.
+        #[serde(default = "default_max_age_secs")]
+        max_age_secs: u64,
+        #[serde(default)]
+        style: OverlayStyle,
+    },
+}
+
+/// Timezone of a time overlay: "local" for the system timezone, or an IANA name like
+/// "UTC" or "America/Sao_Paulo".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum TimeZoneConfig {
+    Local,
+    Named(Tz),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TemperatureUnit {
+    #[default]
+    Celsius,
+    Fahrenheit,
+    Kelvin,
+}
+
+impl TemperatureUnit {
+    /// Converts a temperature in degrees Celsius to this unit.
+    pub fn convert_celsius(&self, celsius: f64) -> f64 {
+        match self {
+            TemperatureUnit::Celsius => celsius,
+            TemperatureUnit::Fahrenheit => celsius * 9.0 / 5.0 + 32.0,
+            TemperatureUnit::Kelvin => celsius + 273.15,
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            TemperatureUnit::Celsius => "°C",
+            TemperatureUnit::Fahrenheit => "°F",
+            TemperatureUnit::Kelvin => "K",
+        }
+    }
+}
+
+/// How the text of an overlay is drawn. Overlays with the same anchor are stacked in
+/// the order of the configuration, from top to bottom.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OverlayStyle {
+    pub anchor: TextPosition,
+    /// Text colour as [red, green, blue].
+    pub color: [u8; 3],
+    /// Built-in font, used when no font file is set. It can only draw ASCII text.
+    pub font: OverlayFont,
+    /// TrueType or OpenType font file, which can draw any character it contains.
+    pub font_file: Option<PathBuf>,
+    /// Size of the text. With a font file, the text is 32 pixels high at scale 1.
+    pub scale: f64,
+    /// Line thickness of the built-in font.
+    pub thickness: u32,
+    pub background: Option<BackgroundConfig>,
+    /// Text colour used instead of `color` when the data is stale or couldn't be read.
+    pub stale_color: [u8; 3],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverlayFont {
+    #[default]
+    Simplex,
+    Plain,
+    Duplex,
+    Complex,
+    Triplex,
+    ComplexSmall,
+    ScriptSimplex,
+    ScriptComplex,
+}
+
+/// Box drawn behind each line of text.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackgroundConfig {
+    /// Box colour as [red, green, blue].
+    pub color: [u8; 3],
+    /// From 0 (transparent) to 1 (opaque).
+    pub opacity: f64,
+    /// Space between the text and the edges of the box, in pixels.
+    pub padding: u32,
+}
+
+fn default_time_format() -> String {
+    DEFAULT_TIME_FORMAT.to_string()
+}
+
+pub(crate) fn default_w1_sysfs_root() -> PathBuf {
+    PathBuf::from(DEFAULT_W1_SYSFS_ROOT)
+}
+
+pub(crate) fn default_max_age_secs() -> u64 {
+    DEFAULT_MAX_AGE_SECS
+}
+
+impl Default for TimeZoneConfig {
+    fn default() -> Self {
+        TimeZoneConfig::Named(Tz::UTC)
+    }
+}
+
+impl TryFrom<String> for TimeZoneConfig {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        if name == "local" {
+            return Ok(TimeZoneConfig::Local);
+        }
+        name.parse()
+            .map(TimeZoneConfig::Named)
+            .map_err(|_| format!("unknown timezone \"{}\"", name))
+    }
+}
+
+impl Default for OverlayStyle {
+    fn default() -> Self {
+        Self {
+            anchor: TextPosition::BottomRight,
+            color: DEFAULT_TEXT_COLOR,
+            font: OverlayFont::default(),
+            font_file: None,
+            scale: DEFAULT_FONT_SCALE,
+            thickness: DEFAULT_FONT_THICKNESS,
+            background: None,
+            stale_color: DEFAULT_STALE_COLOR,
+        }
+    }
+}
+
+impl Default for BackgroundConfig {
+    fn default() -> Self {
+        Self {
+            color: [0, 0, 0],
+            opacity: DEFAULT_BACKGROUND_OPACITY,
+            padding: DEFAULT_BACKGROUND_PADDING,
+        }
+    }
+}
+
+impl OverlayConfig {
+    pub fn style(&self) -> &OverlayStyle {
+        match self {
+            OverlayConfig::Time { style, .. } => style,
+            OverlayConfig::Temperature { style, .. } => style,
+        }
+    }
+
+    /// Age after which the data of the overlay is stale, None when it can't be.
+    pub fn max_age(&self) -> Option<Duration> {
+        match self {
+            OverlayConfig::Time { .. } => None,
+            OverlayConfig::Temperature { max_age_secs, .. } => {
+                Some(Duration::from_secs(*max_age_secs))
+            }
+        }
+    }
+
+    pub(crate) fn validate(&self, key: &str) -> ConfigResult<()> {
+        match self {
+            OverlayConfig::Time { format, .. } => {
+                if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                    return Err(ConfigError::invalid(
+                        format!("{}.format", key),
+                        format!("invalid time format \"{}\"", format),
+                    ));
+                }
+            }
+            OverlayConfig::Temperature {
+                sensor_id,
+                offset,
+                max_age_secs,
+                ..
+            } => {
+                if sensor_id.as_ref().is_some_and(|id| id.trim().is_empty()) {
+                    return Err(ConfigError::invalid(
+                        format!("{}.sensor_id", key),
+                        "must not be empty",
+                    ));
+                }
+                if !offset.is_finite() {
+                    return Err(ConfigError::invalid(
+                        format!("{}.offset", key),
+                        "must be a number",
+                    ));
+                }
+                if *max_age_secs == 0 {
+                    return Err(ConfigError::invalid(
+                        format!("{}.max_age_secs", key),
+                        "must be greater than 0",
+                    ));
+                }
+            }
+        }
+        self.style().validate(&format!("{}.style", key))
+    }
+}
+
+impl OverlayStyle {
+    fn validate(&self, key: &str) -> ConfigResult<()> {
+        if !self.scale.is_finite() || self.scale <= 0.0 {
+            return Err(ConfigError::invalid(
+                format!("{}.scale", key),
+                "must be greater than 0",
+            ));
+        }
+        if self.thickness == 0 {
+            return Err(ConfigError::invalid(
+                format!("{}.thickness", key),
+                "must be greater than 0",
+            ));
+        }
+        if let Some(font_file) = &self.font_file {
+            if let Err(e) = TrueTypeFont::load(font_file) {
+                return Err(ConfigError::invalid(
+                    format!("{}.font_file", key),
+                    e.to_string(),
+                ));
+            }
+        }
+        if let Some(background) = &self.background {
+            if !(0.0..=1.0).contains(&background.opacity) {
+                return Err(ConfigError::invalid(
+                    format!("{}.background.opacity", key),
+                    format!("must be between 0 and 1, got {}", background.opacity),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert_celsius_to_every_unit() {
+        assert_eq!(TemperatureUnit::Celsius.convert_celsius(21.5), 21.5);
+        assert_eq!(TemperatureUnit::Fahrenheit.convert_celsius(100.0), 212.0);
+        assert_eq!(TemperatureUnit::Fahrenheit.convert_celsius(-40.0), -40.0);
+        assert_eq!(TemperatureUnit::Kelvin.convert_celsius(0.0), 273.15);
+    }
+}
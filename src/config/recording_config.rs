@@ -0,0 +1,392 @@
+use crate::config::{ConfigError, ConfigResult, FrameAnnotation, OverlayConfig};
+use chrono::format::{Item, StrftimeItems};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const DEFAULT_RECORDING_DIRECTORY: &str = "recordings";
+const DEFAULT_CODEC: &str = "mp4v";
+const DEFAULT_CONTAINER: &str = "mp4";
+const DEFAULT_FILE_NAME: &str = "{channel}_{timestamp}";
+const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y%m%d_%H%M%S";
+const DEFAULT_PRE_ROLL_SECS: u64 = 5;
+const DEFAULT_POST_ROLL_SECS: u64 = 10;
+/// Longest pre-roll, as its frames are kept in memory.
+const MAX_PRE_ROLL_SECS: u64 = 60;
+const DEFAULT_PRE_ROLL_MAX_MB: u64 = 256;
+const DEFAULT_SEGMENT_SECS: u64 = 600;
+const DEFAULT_MIN_FREE_SPACE_MB: u64 = 1024;
+const DEFAULT_TIMELAPSE_INTERVAL_SECS: u64 = 60;
+const DEFAULT_TIMELAPSE_FPS: u32 = 30;
+const MAX_TIMELAPSE_FPS: u32 = 120;
+
+/// Video files written from the channels. Motion clips are written to the "motion"
+/// subdirectory of `directory`, continuous segments to "continuous" and timelapses to
+/// "timelapse".
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecordingConfig {
+    /// Directory of the video files, created when missing.
+    pub directory: PathBuf,
+    /// FourCC code of the codec, e.g. "mp4v", "avc1" or "MJPG".
+    pub codec: String,
+    /// Extension of the files, which selects the container, e.g. "mp4", "avi" or "mkv".
+    pub container: String,
+    /// Name of the files without extension. "{channel}" is replaced by the channel name
+    /// and "{timestamp}" by the time the recording started.
+    pub file_name: String,
+    /// strftime-like format of "{timestamp}", in local time.
+    pub timestamp_format: String,
+    /// What is drawn over the recorded frames. Raw by default, so the recordings show
+    /// exactly what the cameras saw.
+    pub annotation: FrameAnnotation,
+    /// Records clips while something moves. No clip is recorded when it is not set.
+    pub motion: Option<MotionRecordingConfig>,
+    /// Records everything in segments of fixed length. Nothing is recorded continuously
+    /// when it is not set.
+    pub continuous: Option<ContinuousRecordingConfig>,
+    /// Records a frame every interval in a file per day. No timelapse is recorded when
+    /// it is not set.
+    pub timelapse: Option<TimelapseConfig>,
+    pub retention: RetentionConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MotionRecordingConfig {
+    /// Channels to record. Defaults to every channel.
+    pub channels: Option<Vec<String>>,
+    /// Time before the motion started that is included in the clip. Its frames are kept
+    /// uncompressed in memory, width x height x 3 bytes each: 5 seconds of 1080p at 25
+    /// fps take about 780 MB per channel.
+    #[serde(default = "default_pre_roll_secs")]
+    pub pre_roll_secs: u64,
+    /// Memory the pre-roll of a channel may take. The oldest frames are dropped above it,
+    /// which shortens the pre-roll of large or fast streams.
+    #[serde(default = "default_pre_roll_max_mb")]
+    pub pre_roll_max_mb: u64,
+    /// Time the recording goes on after the motion stopped.
+    #[serde(default = "default_post_roll_secs")]
+    pub post_roll_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ContinuousRecordingConfig {
+    /// Channels to record. Defaults to every channel.
+    pub channels: Option<Vec<String>>,
+    /// Length of each file.
+    #[serde(default = "default_segment_secs")]
+    pub segment_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimelapseConfig {
+    /// Channels to record. Defaults to every channel.
+    pub channels: Option<Vec<String>>,
+    /// Time between the recorded frames.
+    #[serde(default = "default_timelapse_interval_secs")]
+    pub interval_secs: u64,
+    /// Frame rate of the files.
+    #[serde(default = "default_timelapse_fps")]
+    pub fps: u32,
+    /// Time overlay drawn over the recorded frames, e.g.
+    /// `{ type = "time", format = "%Y-%m-%d %H:%M" }`.
+    pub timestamp: Option<OverlayConfig>,
+}
+
+/// Limits of the recording directory, checked every minute. The oldest files are
+/// deleted first. Only the motion clips and the continuous segments are deleted, never
+/// the files being recorded, the timelapses or any other file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// Files older than this are deleted.
+    pub max_age_hours: Option<u64>,
+    /// Total size of the motion clips and continuous segments above which the oldest
+    /// ones are deleted.
+    pub max_size_mb: Option<u64>,
+    /// Free space of the disk under which an alert is shown.
+    pub min_free_space_mb: u64,
+}
+
+fn default_pre_roll_secs() -> u64 {
+    DEFAULT_PRE_ROLL_SECS
+}
+
+fn default_pre_roll_max_mb() -> u64 {
+    DEFAULT_PRE_ROLL_MAX_MB
+}
+
+fn default_post_roll_secs() -> u64 {
+    DEFAULT_POST_ROLL_SECS
+}
+
+fn default_segment_secs() -> u64 {
+    DEFAULT_SEGMENT_SECS
+}
+
+fn default_timelapse_interval_secs() -> u64 {
+    DEFAULT_TIMELAPSE_INTERVAL_SECS
+}
+
+fn default_timelapse_fps() -> u32 {
+    DEFAULT_TIMELAPSE_FPS
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from(DEFAULT_RECORDING_DIRECTORY),
+            codec: DEFAULT_CODEC.to_string(),
+            container: DEFAULT_CONTAINER.to_string(),
+            file_name: DEFAULT_FILE_NAME.to_string(),
+            timestamp_format: DEFAULT_TIMESTAMP_FORMAT.to_string(),
+            annotation: FrameAnnotation::Raw,
+            motion: None,
+            continuous: None,
+            timelapse: None,
+            retention: RetentionConfig::default(),
+        }
+    }
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            max_age_hours: None,
+            max_size_mb: None,
+            min_free_space_mb: DEFAULT_MIN_FREE_SPACE_MB,
+        }
+    }
+}
+
+impl RecordingConfig {
+    /// Motion recording settings of the channel, None when it isn't recorded.
+    pub fn motion_for(&self, channel_name: &str) -> Option<&MotionRecordingConfig> {
+        self.motion
+            .as_ref()
+            .filter(|motion| records_channel(&motion.channels, channel_name))
+    }
+
+    /// Continuous recording settings of the channel, None when it isn't recorded.
+    pub fn continuous_for(&self, channel_name: &str) -> Option<&ContinuousRecordingConfig> {
+        self.continuous
+            .as_ref()
+            .filter(|continuous| records_channel(&continuous.channels, channel_name))
+    }
+
+    /// Timelapse settings of the channel, None when it isn't recorded.
+    pub fn timelapse_for(&self, channel_name: &str) -> Option<&TimelapseConfig> {
+        self.timelapse
+            .as_ref()
+            .filter(|timelapse| records_channel(&timelapse.channels, channel_name))
+    }
+
+    /// Whether some channel may be recorded.
+    pub fn is_enabled(&self) -> bool {
+        self.motion.is_some() || self.continuous.is_some() || self.timelapse.is_some()
+    }
+
+    pub(crate) fn validate(&self, channel_names: &HashSet<&str>) -> ConfigResult<()> {
+        if self.codec.len() != 4 || !self.codec.is_ascii() {
+            return Err(ConfigError::invalid(
+                "recording.codec",
+                format!(
+                    "must be a FourCC code of 4 characters, got \"{}\"",
+                    self.codec
+                ),
+            ));
+        }
+        let is_extension =
+            !self.container.is_empty() && self.container.chars().all(|c| c.is_ascii_alphanumeric());
+        if !is_extension {
+            return Err(ConfigError::invalid(
+                "recording.container",
+                format!(
+                    "must be a file extension like \"mp4\", got \"{}\"",
+                    self.container
+                ),
+            ));
+        }
+        if !self.file_name.contains("{timestamp}") {
+            return Err(ConfigError::invalid(
+                "recording.file_name",
+                "must contain \"{timestamp}\", so recordings don't overwrite each other",
+            ));
+        }
+        validate_timestamp("recording.timestamp_format", &self.timestamp_format)?;
+
+        if let Some(motion) = &self.motion {
+            motion.validate(channel_names)?;
+        }
+        if let Some(continuous) = &self.continuous {
+            continuous.validate(channel_names)?;
+        }
+        if let Some(timelapse) = &self.timelapse {
+            timelapse.validate(channel_names)?;
+        }
+        self.retention.validate()
+    }
+}
+
+impl MotionRecordingConfig {
+    pub fn pre_roll(&self) -> Duration {
+        Duration::from_secs(self.pre_roll_secs)
+    }
+
+    pub fn pre_roll_max_bytes(&self) -> u64 {
+        self.pre_roll_max_mb * 1024 * 1024
+    }
+
+    pub fn post_roll(&self) -> Duration {
+        Duration::from_secs(self.post_roll_secs)
+    }
+
+    fn validate(&self, channel_names: &HashSet<&str>) -> ConfigResult<()> {
+        validate_channels("recording.motion.channels", &self.channels, channel_names)?;
+        if self.pre_roll_secs > MAX_PRE_ROLL_SECS {
+            return Err(ConfigError::invalid(
+                "recording.motion.pre_roll_secs",
+                format!(
+                    "must be at most {}, got {}",
+                    MAX_PRE_ROLL_SECS, self.pre_roll_secs
+                ),
+            ));
+        }
+        if self.pre_roll_max_mb == 0 {
+            return Err(ConfigError::invalid(
+                "recording.motion.pre_roll_max_mb",
+                "must be greater than 0",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl ContinuousRecordingConfig {
+    pub fn segment_length(&self) -> Duration {
+        Duration::from_secs(self.segment_secs)
+    }
+
+    fn validate(&self, channel_names: &HashSet<&str>) -> ConfigResult<()> {
+        validate_channels(
+            "recording.continuous.channels",
+            &self.channels,
+            channel_names,
+        )?;
+        if self.segment_secs == 0 {
+            return Err(ConfigError::invalid(
+                "recording.continuous.segment_secs",
+                "must be greater than 0",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl TimelapseConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+
+    fn validate(&self, channel_names: &HashSet<&str>) -> ConfigResult<()> {
+        validate_channels(
+            "recording.timelapse.channels",
+            &self.channels,
+            channel_names,
+        )?;
+        if self.interval_secs == 0 {
+            return Err(ConfigError::invalid(
+                "recording.timelapse.interval_secs",
+                "must be greater than 0",
+            ));
+        }
+        if !(1..=MAX_TIMELAPSE_FPS).contains(&self.fps) {
+            return Err(ConfigError::invalid(
+                "recording.timelapse.fps",
+                format!(
+                    "must be between 1 and {}, got {}",
+                    MAX_TIMELAPSE_FPS, self.fps
+                ),
+            ));
+        }
+        match &self.timestamp {
+            Some(timestamp @ OverlayConfig::Time { .. }) => {
+                timestamp.validate("recording.timelapse.timestamp")
+            }
+            Some(_) => Err(ConfigError::invalid(
+                "recording.timelapse.timestamp.type",
+                "must be \"time\"",
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+impl RetentionConfig {
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age_hours
+            .map(|hours| Duration::from_secs(hours * 60 * 60))
+    }
+
+    pub fn max_size_bytes(&self) -> Option<u64> {
+        self.max_size_mb.map(|size| size * 1024 * 1024)
+    }
+
+    pub fn min_free_space_bytes(&self) -> u64 {
+        self.min_free_space_mb * 1024 * 1024
+    }
+
+    fn validate(&self) -> ConfigResult<()> {
+        if self.max_age_hours == Some(0) {
+            return Err(ConfigError::invalid(
+                "recording.retention.max_age_hours",
+                "must be greater than 0",
+            ));
+        }
+        if self.max_size_mb == Some(0) {
+            return Err(ConfigError::invalid(
+                "recording.retention.max_size_mb",
+                "must be greater than 0",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A missing channel list selects every channel.
+pub(super) fn records_channel(channels: &Option<Vec<String>>, channel_name: &str) -> bool {
+    match channels {
+        Some(channels) => channels.iter().any(|channel| channel == channel_name),
+        None => true,
+    }
+}
+
+pub(super) fn validate_channels(
+    key: &str,
+    channels: &Option<Vec<String>>,
+    channel_names: &HashSet<&str>,
+) -> ConfigResult<()> {
+    for (index, channel) in channels.iter().flatten().enumerate() {
+        if !channel_names.contains(channel.as_str()) {
+            return Err(ConfigError::invalid(
+                format!("{}[{}]", key, index),
+                format!("unknown channel \"{}\"", channel),
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub(super) fn validate_timestamp(key: &str, format: &str) -> ConfigResult<()> {
+    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+        return Err(ConfigError::invalid(
+            key,
+            format!("invalid time format \"{}\"", format),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,197 @@
+use crate::config::recording_config::{records_channel, validate_channels, validate_timestamp};
+use crate::config::{ConfigError, ConfigResult, FrameAnnotation};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const DEFAULT_SNAPSHOT_DIRECTORY: &str = ".";
+const DEFAULT_JPEG_QUALITY: u8 = 90;
+const DEFAULT_PNG_COMPRESSION: u8 = 3;
+const DEFAULT_FILE_NAME: &str = "snapshot_{channel}_{timestamp}";
+const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y%m%d_%H%M%S%.3f";
+const DEFAULT_BEST_FRAME_SECS: u64 = 2;
+/// Longest search for the best frame of a motion event, as the best frame is kept in memory.
+const MAX_BEST_FRAME_SECS: u64 = 60;
+const DEFAULT_SNAPSHOT_INTERVAL_SECS: u64 = 60;
+const MAX_PNG_COMPRESSION: u8 = 9;
+
+/// Still images of the channels, taken with the snapshot key, on motion and on an
+/// interval.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SnapshotConfig {
+    /// Directory of the images, created when missing.
+    pub directory: PathBuf,
+    pub format: ImageFormat,
+    /// Quality of the JPEG images, from 1 to 100.
+    pub jpeg_quality: u8,
+    /// Compression level of the PNG images, from 0 to 9.
+    pub png_compression: u8,
+    /// Name of the files without extension. "{channel}" is replaced by the channel name,
+    /// "{trigger}" by "key", "motion" or "interval" and "{timestamp}" by the time the
+    /// snapshot was taken.
+    pub file_name: String,
+    /// strftime-like format of "{timestamp}", in local time.
+    pub timestamp_format: String,
+    /// What is drawn over the snapshots. Those taken with the key are the latest frame
+    /// of the current channel, whatever the layout.
+    pub annotation: FrameAnnotation,
+    /// File to which a JSON line is appended for every snapshot, for other tools to
+    /// pick them up.
+    pub events_file: Option<PathBuf>,
+    /// Takes a snapshot of every motion event. No snapshot is taken on motion when it
+    /// is not set.
+    pub motion: Option<MotionSnapshotConfig>,
+    /// Takes snapshots at a fixed interval. No snapshot is taken on an interval when it
+    /// is not set.
+    pub interval: Option<IntervalSnapshotConfig>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageFormat {
+    Jpeg,
+    #[default]
+    Png,
+}
+
+impl ImageFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Png => "png",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MotionSnapshotConfig {
+    /// Channels to take snapshots of. Defaults to every channel.
+    pub channels: Option<Vec<String>>,
+    /// Time from the start of the motion in which the frame with the largest moving
+    /// area is chosen.
+    #[serde(default = "default_best_frame_secs")]
+    pub best_frame_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IntervalSnapshotConfig {
+    /// Channels to take snapshots of. Defaults to every channel.
+    pub channels: Option<Vec<String>>,
+    #[serde(default = "default_snapshot_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_best_frame_secs() -> u64 {
+    DEFAULT_BEST_FRAME_SECS
+}
+
+fn default_snapshot_interval_secs() -> u64 {
+    DEFAULT_SNAPSHOT_INTERVAL_SECS
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from(DEFAULT_SNAPSHOT_DIRECTORY),
+            format: ImageFormat::default(),
+            jpeg_quality: DEFAULT_JPEG_QUALITY,
+            png_compression: DEFAULT_PNG_COMPRESSION,
+            file_name: DEFAULT_FILE_NAME.to_string(),
+            timestamp_format: DEFAULT_TIMESTAMP_FORMAT.to_string(),
+            annotation: FrameAnnotation::Raw,
+            events_file: None,
+            motion: None,
+            interval: None,
+        }
+    }
+}
+
+impl SnapshotConfig {
+    /// Motion snapshot settings of the channel, None when none are taken.
+    pub fn motion_for(&self, channel_name: &str) -> Option<&MotionSnapshotConfig> {
+        self.motion
+            .as_ref()
+            .filter(|motion| records_channel(&motion.channels, channel_name))
+    }
+
+    /// Interval snapshot settings of the channel, None when none are taken.
+    pub fn interval_for(&self, channel_name: &str) -> Option<&IntervalSnapshotConfig> {
+        self.interval
+            .as_ref()
+            .filter(|interval| records_channel(&interval.channels, channel_name))
+    }
+
+    pub(crate) fn validate(&self, channel_names: &HashSet<&str>) -> ConfigResult<()> {
+        if !(1..=100).contains(&self.jpeg_quality) {
+            return Err(ConfigError::invalid(
+                "snapshots.jpeg_quality",
+                format!("must be between 1 and 100, got {}", self.jpeg_quality),
+            ));
+        }
+        if self.png_compression > MAX_PNG_COMPRESSION {
+            return Err(ConfigError::invalid(
+                "snapshots.png_compression",
+                format!(
+                    "must be between 0 and {}, got {}",
+                    MAX_PNG_COMPRESSION, self.png_compression
+                ),
+            ));
+        }
+        if !self.file_name.contains("{timestamp}") {
+            return Err(ConfigError::invalid(
+                "snapshots.file_name",
+                "must contain \"{timestamp}\", so snapshots don't overwrite each other",
+            ));
+        }
+        validate_timestamp("snapshots.timestamp_format", &self.timestamp_format)?;
+
+        if let Some(motion) = &self.motion {
+            motion.validate(channel_names)?;
+        }
+        if let Some(interval) = &self.interval {
+            interval.validate(channel_names)?;
+        }
+        Ok(())
+    }
+}
+
+impl MotionSnapshotConfig {
+    pub fn best_frame_window(&self) -> Duration {
+        Duration::from_secs(self.best_frame_secs)
+    }
+
+    fn validate(&self, channel_names: &HashSet<&str>) -> ConfigResult<()> {
+        validate_channels("snapshots.motion.channels", &self.channels, channel_names)?;
+        if self.best_frame_secs > MAX_BEST_FRAME_SECS {
+            return Err(ConfigError::invalid(
+                "snapshots.motion.best_frame_secs",
+                format!(
+                    "must be at most {}, got {}",
+                    MAX_BEST_FRAME_SECS, self.best_frame_secs
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl IntervalSnapshotConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+
+    fn validate(&self, channel_names: &HashSet<&str>) -> ConfigResult<()> {
+        validate_channels("snapshots.interval.channels", &self.channels, channel_names)?;
+        if self.interval_secs == 0 {
+            return Err(ConfigError::invalid(
+                "snapshots.interval.interval_secs",
+                "must be greater than 0",
+            ));
+        }
+        Ok(())
+    }
+}
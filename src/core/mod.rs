@@ -1,3 +1,5 @@
 mod omni_pane;
+pub(crate) mod scheduler;
+pub(crate) mod shutdown;
 
 pub use omni_pane::OmniPane;
@@ -1,84 +1,611 @@
-use crate::overlay_text_providers::OverlayTextProvider;
+use crate::channel_selectors;
+use crate::channel_selectors::{ChannelInfo, ChannelSelector, SelectionContext};
+use crate::config::{
+    BackgroundConfig, ConfigWatcher, DisplayConfig, LayoutMode, OmniPaneConfig, OverlayStyle,
+    PictureInPictureConfig, RecordingConfig, SnapshotConfig,
+};
+use crate::core::scheduler::Scheduler;
+use crate::overlay_text_providers;
+use crate::overlay_text_providers::Overlay;
 use crate::video_display::display::DisplayWindow;
 use crate::video_display::image_manipulation;
-use crate::video_display::video_channel::{VideoChannel};
-use opencv::core::Mat;
-use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use crate::video_display::image_manipulation::TextPosition;
+use crate::video_display::key_bindings::{DisplayCommand, KeyBindings};
+use crate::video_display::layout;
+use crate::video_display::recording::ChannelRecordingSettings;
+use crate::video_display::retention::{DiskAlert, RetentionManager, RETENTION_CHECK_INTERVAL};
+use crate::video_display::snapshot;
+use crate::video_display::snapshot::{
+    ChannelSnapshotSettings, SnapshotFileSettings, SnapshotTrigger,
+};
+use crate::video_display::text_layout;
+use crate::video_display::text_layout::{OverlayText, TextLine};
+use crate::video_display::text_renderer;
+use crate::video_display::text_renderer::TextRenderer;
+use crate::video_display::video_channel::{VideoChannel, VideoChannelSettings};
+use crate::video_display::{VideoResult, VideoStreamError};
+use opencv::core::{Mat, MatTraitConst, Size};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+/// Threads shared by the periodic services: overlay providers and recording retention.
+const SCHEDULER_WORKERS: usize = 2;
+const ALERT_COLOR: [u8; 3] = [255, 0, 0];
+
 pub struct OmniPane {
     channels: Vec<VideoChannel>,
-    overlay_providers: Vec<Box<dyn OverlayTextProvider>>,
-    pub current_camera_index: Arc<AtomicU8>,
+    overlays: Vec<Overlay>,
+    overlay_services_running: Arc<AtomicBool>,
+    scheduler: Scheduler,
+    recording_config: RecordingConfig,
+    retention_running: Arc<AtomicBool>,
+    disk_alert: DiskAlert,
+    snapshot_config: SnapshotConfig,
+    config_watcher: Option<ConfigWatcher>,
+    display_config: DisplayConfig,
+    label_renderer: Box<dyn TextRenderer>,
+    slate_renderer: Box<dyn TextRenderer>,
+    /// Size of the last frame shown by the 1x1 layout, used for its "no signal" slate.
+    display_size: Size,
+    channel_selector: Box<dyn ChannelSelector>,
+    current_channel: usize,
+    key_bindings: KeyBindings,
+    switching_paused: bool,
+    frozen_frame: Option<Mat>,
+    /// Corner covered by the picture-in-picture inset, and how far the text in that
+    /// corner has to be moved to stay clear of it.
+    inset_corner: Option<(TextPosition, i32)>,
 }
 
 impl OmniPane {
     pub fn new(
         channels: Vec<VideoChannel>,
-        overlay_providers: Vec<Box<dyn OverlayTextProvider>>,
+        overlays: Vec<Overlay>,
+        channel_selector: Box<dyn ChannelSelector>,
+        key_bindings: KeyBindings,
+        display_config: DisplayConfig,
+        recording_config: RecordingConfig,
+        snapshot_config: SnapshotConfig,
     ) -> Self {
         OmniPane {
             channels,
-            overlay_providers,
-            current_camera_index: Arc::new(AtomicU8::new(0)),
+            overlays,
+            overlay_services_running: Arc::new(AtomicBool::new(false)),
+            scheduler: Scheduler::start(SCHEDULER_WORKERS),
+            recording_config,
+            retention_running: Arc::new(AtomicBool::new(false)),
+            disk_alert: DiskAlert::default(),
+            snapshot_config,
+            config_watcher: None,
+            display_size: display_config.size(),
+            label_renderer: layout::label_renderer(display_config.font_file.clone()),
+            slate_renderer: image_manipulation::slate_renderer(display_config.font_file.clone()),
+            display_config,
+            channel_selector,
+            current_channel: 0,
+            key_bindings,
+            switching_paused: false,
+            frozen_frame: None,
+            inset_corner: None,
         }
     }
 
-    pub fn start_display(&mut self, is_running: Arc<AtomicBool>) {
-        // TODO: error handling
-        let main_display = DisplayWindow::new_default().unwrap();
+    /// Applies every valid configuration reported by the watcher while the display is running.
+    pub fn watch_config(&mut self, config_watcher: ConfigWatcher) {
+        self.config_watcher = Some(config_watcher);
+    }
+
+    pub fn start_display(&mut self, is_running: Arc<AtomicBool>) -> VideoResult<()> {
+        let main_display = DisplayWindow::new_default()?;
+
+        self.start_overlay_services();
+        self.start_retention();
+        let result = self.display_loop(&main_display, &is_running);
+        self.overlay_services_running.store(false, Ordering::Relaxed);
+        self.retention_running.store(false, Ordering::Relaxed);
 
+        result
+    }
+
+    fn display_loop(
+        &mut self,
+        main_display: &DisplayWindow,
+        is_running: &AtomicBool,
+    ) -> VideoResult<()> {
         while is_running.load(Ordering::Relaxed) {
-            let camera_index = self.get_safe_camera_index();
-            let camera_stream = &mut self.channels[camera_index as usize];
+            if let Some(config) = self.config_watcher.as_ref().and_then(|w| w.take_update()) {
+                self.apply_config(config);
+            }
 
-            // TODO: error handling
-            let capture_start_time = Instant::now();
-            let mut image = camera_stream.create_frame_image().unwrap();
-            let mut duration = camera_stream.settings.get_frame_duration();
+            let camera_index = if self.switching_paused {
+                self.current_channel
+            } else {
+                self.select_channel()
+            };
 
-            self.draw_overlays(&mut image);
+            let capture_start_time = Instant::now();
+            let mut duration = self.channels[camera_index].settings().get_frame_duration();
 
-            // TODO: error handling
-            main_display.display_frame(&image).unwrap();
+            let overlay_texts = self.overlay_texts();
+            self.share_overlay_texts(&overlay_texts);
+            let image = self.compose_frame(camera_index, &overlay_texts)?;
+            main_display.display_frame(&image)?;
 
-            // TODO: error handling
             if capture_start_time.elapsed() < duration {
                 duration -= capture_start_time.elapsed();
             } else {
                 duration = Duration::from_millis(0);
             }
-            if main_display.stop_key_pressed(duration).unwrap() {
-                // TODO: exit all services
-                break;
+            match main_display.wait_command(duration, &self.key_bindings)? {
+                Some(DisplayCommand::Quit) => break,
+                Some(command) => self.handle_command(command)?,
+                None => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stops every service started by the display and releases the video streams.
+    /// Returns false if some service thread didn't stop before the deadline.
+    pub fn shutdown(&mut self, deadline: Instant) -> bool {
+        self.overlay_services_running.store(false, Ordering::Relaxed);
+
+        let mut all_stopped = self.scheduler.join(deadline);
+        if let Some(config_watcher) = &mut self.config_watcher {
+            all_stopped &= config_watcher.join(deadline);
+        }
+
+        for channel in &self.channels {
+            channel.stop();
+        }
+        for channel in &mut self.channels {
+            all_stopped &= channel.join(deadline);
+        }
+
+        all_stopped
+    }
+
+    fn start_overlay_services(&mut self) {
+        /*
+        Every set of providers gets its own running flag, so the scheduler drops
+        the tasks of replaced providers without affecting the new ones.
+        */
+        self.overlay_services_running.store(false, Ordering::Relaxed);
+        self.overlay_services_running = Arc::new(AtomicBool::new(true));
+
+        for overlay in &mut self.overlays {
+            overlay.provider.start_service(
+                &self.scheduler,
+                self.overlay_services_running.clone(),
+            );
+        }
+    }
+
+    /// Checks the limits of the recording directory periodically, when something is
+    /// recorded.
+    fn start_retention(&mut self) {
+        self.retention_running.store(false, Ordering::Relaxed);
+        self.retention_running = Arc::new(AtomicBool::new(true));
+        self.disk_alert = DiskAlert::default();
+
+        if let Some(mut retention_manager) =
+            RetentionManager::from_config(&self.recording_config, self.disk_alert.clone())
+        {
+            self.scheduler.schedule(
+                "recording retention",
+                RETENTION_CHECK_INTERVAL,
+                self.retention_running.clone(),
+                move || retention_manager.run(),
+            );
+        }
+    }
+
+    /// Creates the image of the layout with the overlays, the paused and frozen markers
+    /// and the disk alert on it, or the frozen image while the display is frozen.
+    fn compose_frame(
+        &mut self,
+        camera_index: usize,
+        overlay_texts: &[OverlayText],
+    ) -> VideoResult<Mat> {
+        let mut image = match &self.frozen_frame {
+            Some(frozen_frame) => frozen_frame.clone(),
+            None => self.create_layout_image(camera_index)?,
+        };
+        let (status, alert) = (self.display_status(), self.disk_alert());
+        self.draw_overlays(&mut image, status, alert, overlay_texts)?;
+        Ok(image)
+    }
+
+    fn current_layout(&self, camera_index: usize) -> LayoutMode {
+        match self.display_config.motion_layout {
+            Some(motion_layout) if self.has_recent_motion(camera_index) => motion_layout,
+            _ => self.display_config.layout,
+        }
+    }
+
+    /*
+    The result of the movement check is replaced on every check, and goes on and off
+    while small regions come and go, so the motion layout is kept until nothing moved
+    on the channel for the hold time.
+    */
+    fn has_recent_motion(&self, camera_index: usize) -> bool {
+        let motion = self.channels[camera_index].motion();
+        motion.has_motion()
+            || motion.last_motion.is_some_and(|last_motion| {
+                last_motion.elapsed() < self.display_config.motion_layout_hold()
+            })
+    }
+
+    fn create_layout_image(&mut self, camera_index: usize) -> VideoResult<Mat> {
+        self.inset_corner = None;
+
+        let layout = self.current_layout(camera_index);
+        if layout == LayoutMode::Single {
+            let mut image = self.create_channel_image(camera_index, self.display_size)?;
+            self.display_size = image.size()?;
+            self.draw_picture_in_picture(&mut image, camera_index)?;
+            return Ok(image);
+        }
+
+        let canvas_size = self.display_config.size();
+        let mut canvas = layout::create_canvas(canvas_size)?;
+        for tile in layout::arrange(layout, canvas_size, self.channels.len(), camera_index) {
+            let tile_size = Size::new(tile.area.width, tile.area.height);
+            let image = self.create_channel_image(tile.channel_index, tile_size)?;
+            layout::draw_tile(
+                &mut canvas,
+                &image,
+                tile.area,
+                &self.channels[tile.channel_index].name,
+                self.label_renderer.as_ref(),
+                tile.channel_index == camera_index,
+            )?;
+        }
+        Ok(canvas)
+    }
+
+    fn draw_picture_in_picture(&mut self, image: &mut Mat, camera_index: usize) -> VideoResult<()> {
+        let Some(inset) = self.display_config.picture_in_picture.clone() else {
+            return Ok(());
+        };
+        let Some(inset_index) = self.select_inset_channel(&inset, camera_index) else {
+            return Ok(());
+        };
+
+        let slate_size = Size::new(
+            self.display_size.width * inset.size_percent as i32 / 100,
+            self.display_size.height * inset.size_percent as i32 / 100,
+        );
+        let inset_image = self.create_channel_image(inset_index, slate_size)?;
+        let area = layout::draw_inset(image, &inset_image, &inset)?;
+        if area.height > 0 {
+            self.inset_corner = Some((inset.corner, area.height + inset.margin as i32));
+        }
+        Ok(())
+    }
+
+    /// Finds the channel shown in the inset: the configured one, or else the one with
+    /// the most recent motion. The current channel is never shown in the inset.
+    fn select_inset_channel(
+        &self,
+        inset: &PictureInPictureConfig,
+        camera_index: usize,
+    ) -> Option<usize> {
+        let inset_index = match &inset.channel {
+            Some(name) => self.channels.iter().position(|channel| &channel.name == name),
+            None => self
+                .channels
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| *index != camera_index)
+                .filter_map(|(index, channel)| {
+                    channel.motion().last_motion.map(|last_motion| (index, last_motion))
+                })
+                .max_by_key(|(_, last_motion)| *last_motion)
+                .map(|(index, _)| index),
+        };
+        inset_index.filter(|index| *index != camera_index)
+    }
+
+    /// Distance the text in the given corner has to be moved to stay clear of the inset.
+    fn text_offset(&self, position: TextPosition) -> i32 {
+        match self.inset_corner {
+            Some((corner, offset)) if corner == position => offset,
+            _ => 0,
+        }
+    }
+
+    fn create_channel_image(&mut self, camera_index: usize, slate_size: Size) -> VideoResult<Mat> {
+        let camera_stream = &mut self.channels[camera_index];
+
+        /*
+        A channel that can't deliver frames reconnects on its own, so the display
+        keeps going and shows the "no signal" slate in the meantime.
+        */
+        match camera_stream.create_frame_image(self.display_config.annotation) {
+            Ok(image) => Ok(image),
+            Err(e) => {
+                if !matches!(e, VideoStreamError::StreamUnavailable(_)) {
+                    println!("Could not create frame for camera {}: {}", camera_stream.name, e);
+                }
+                camera_stream.create_slate_image(slate_size, self.slate_renderer.as_ref())
+            }
+        }
+    }
+
+    fn handle_command(&mut self, command: DisplayCommand) -> VideoResult<()> {
+        let channel_count = self.channels.len();
+        match command {
+            DisplayCommand::Quit => {}
+            DisplayCommand::SelectChannel(index) => {
+                if index < channel_count {
+                    self.show_channel(index);
+                }
+            }
+            DisplayCommand::NextChannel => {
+                self.show_channel((self.current_channel + 1) % channel_count);
+            }
+            DisplayCommand::PreviousChannel => {
+                self.show_channel((self.current_channel + channel_count - 1) % channel_count);
             }
+            DisplayCommand::TogglePause => {
+                self.switching_paused = !self.switching_paused;
+                if self.switching_paused {
+                    println!("Automatic switching paused");
+                } else {
+                    println!("Automatic switching resumed");
+                }
+            }
+            DisplayCommand::ToggleFreeze => {
+                self.frozen_frame = match self.frozen_frame.take() {
+                    Some(_) => None,
+                    None => Some(self.create_layout_image(self.current_channel)?),
+                };
+            }
+            DisplayCommand::Snapshot => {
+                if let Err(e) = self.take_key_snapshot() {
+                    println!("Could not save snapshot: {}", e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the latest frame of the current channel, whatever the layout, drawn over as
+    /// the capture threads do for the motion and interval snapshots.
+    fn take_key_snapshot(&mut self) -> VideoResult<()> {
+        let annotation = self.snapshot_config.annotation;
+        let channel = &mut self.channels[self.current_channel];
+        let mut image = channel.create_frame_image(annotation)?;
+        let channel_name = channel.name.clone();
+
+        if annotation.draws_overlays() {
+            let overlay_texts = self.overlay_texts();
+            let lines: Vec<_> = overlay_texts.iter().map(OverlayText::line).collect();
+            text_layout::draw_text_lines(&mut image, &lines, |_| 0)?;
         }
+
+        let settings = SnapshotFileSettings::from_config(&self.snapshot_config);
+        snapshot::save_snapshot(&image, &channel_name, SnapshotTrigger::Key, &settings)?;
+        Ok(())
     }
 
-    fn draw_overlays(&mut self, mut image: &mut Mat) {
-        let mut line_index: u8 = 0;
-        for overlay_provider in &self.overlay_providers {
-            let text = overlay_provider.get_text();
-            image_manipulation::write_text(
-                &mut image,
-                line_index,
-                &text,
-                image_manipulation::TextPosition::BottomRight,
+    /// Shows the channel chosen by the operator. Automatic switching stays paused until
+    /// it is resumed from the keyboard.
+    fn show_channel(&mut self, camera_index: usize) {
+        if !self.switching_paused {
+            println!("Automatic switching paused");
+            self.switching_paused = true;
+        }
+        self.frozen_frame = None;
+        if camera_index != self.current_channel {
+            println!(
+                "Changing camera to {} (index {})",
+                self.channels[camera_index].name, camera_index
             );
-            if line_index == u8::MAX {
-                break;
+            self.current_channel = camera_index;
+        }
+    }
+
+    fn disk_alert(&self) -> Option<String> {
+        self.disk_alert
+            .lock()
+            .map_or(None, |disk_alert| disk_alert.clone())
+    }
+
+    fn display_status(&self) -> Option<&'static str> {
+        match (self.switching_paused, self.frozen_frame.is_some()) {
+            (false, false) => None,
+            (true, false) => Some("Paused"),
+            (false, true) => Some("Frozen"),
+            (true, true) => Some("Paused, frozen"),
+        }
+    }
+
+    fn apply_config(&mut self, config: OmniPaneConfig) {
+        let current_channel_name = self
+            .channels
+            .get(self.current_channel)
+            .map(|channel| channel.name.clone());
+
+        let mut old_channels: Vec<Option<VideoChannel>> =
+            self.channels.drain(..).map(Some).collect();
+        let mut channels: Vec<VideoChannel> = Vec::new();
+
+        for channel_config in &config.channels {
+            let existing_channel = old_channels
+                .iter_mut()
+                .find(|channel| {
+                    channel.as_ref().is_some_and(|c| {
+                        c.name == channel_config.name && c.url == channel_config.url
+                    })
+                })
+                .and_then(Option::take);
+
+            match existing_channel {
+                Some(mut channel) => {
+                    channel.update_settings(VideoChannelSettings::from_config(channel_config));
+                    channel.update_recording(ChannelRecordingSettings::from_config(
+                        &config.recording,
+                        &channel_config.name,
+                    ));
+                    channel.update_snapshots(ChannelSnapshotSettings::from_config(
+                        &config.snapshots,
+                        &channel_config.name,
+                    ));
+                    channels.push(channel);
+                }
+                None => {
+                    channels.push(VideoChannel::open(
+                        channel_config,
+                        &config.recording,
+                        &config.snapshots,
+                    ));
+                    println!(
+                        "Added camera {} for url {}",
+                        channel_config.name, channel_config.url
+                    );
+                }
             }
-            line_index += 1;
+        }
+
+        for removed_channel in old_channels.into_iter().flatten() {
+            println!("Removed camera {}", removed_channel.name);
+        }
+
+        let camera_index = current_channel_name
+            .and_then(|name| channels.iter().position(|channel| channel.name == name))
+            .unwrap_or(0);
+
+        self.channels = channels;
+        self.current_channel = camera_index;
+        self.channel_selector = channel_selectors::create_selector(&config);
+        self.key_bindings = KeyBindings::from_config(&config.keys);
+        if self.display_config.font_file != config.display.font_file {
+            self.label_renderer = layout::label_renderer(config.display.font_file.clone());
+            self.slate_renderer =
+                image_manipulation::slate_renderer(config.display.font_file.clone());
+        }
+        self.display_config = config.display.clone();
+        self.frozen_frame = None;
+        self.snapshot_config = config.snapshots.clone();
+        if self.recording_config != config.recording {
+            self.recording_config = config.recording.clone();
+            self.start_retention();
+        }
+
+        self.overlays = config
+            .overlays
+            .iter()
+            .map(overlay_text_providers::create_overlay)
+            .collect();
+        self.start_overlay_services();
+    }
+
+    /// Text of every overlay, with one line per reading and the marker of stale data.
+    fn overlay_texts(&self) -> Vec<OverlayText> {
+        let mut overlay_texts = Vec::with_capacity(self.overlays.len());
+        for overlay in &self.overlays {
+            // Providers with several readings return one line per reading
+            for (text, data_marker) in overlay.marked_lines() {
+                let (text, color) = match data_marker {
+                    Some(marker) => (
+                        format!("{} {}", text, marker.text()),
+                        overlay.style.stale_color,
+                    ),
+                    None => (text, overlay.style.color),
+                };
+                overlay_texts.push(OverlayText {
+                    text,
+                    style: overlay.style.clone(),
+                    color,
+                    renderer: overlay.renderer.clone(),
+                });
+            }
+        }
+        overlay_texts
+    }
+
+    /// Hands the overlay text to the channels when their recordings or snapshots are
+    /// composited.
+    fn share_overlay_texts(&self, overlay_texts: &[OverlayText]) {
+        if !self.recording_config.annotation.draws_overlays()
+            && !self.snapshot_config.annotation.draws_overlays()
+        {
+            return;
+        }
+        for channel in &self.channels {
+            channel.update_overlay_texts(overlay_texts.to_vec());
         }
     }
 
-    fn get_safe_camera_index(&self) -> u8 {
-        let mut current_index = self.current_camera_index.load(Ordering::Relaxed);
-        if current_index >= self.channels.len() as u8 {
-            println!("Wrong camera index {}", current_index);
-            current_index = 0;
+    /// Draws the display status and the alert in the top left corner, and the text of
+    /// the overlays when the display is composited.
+    fn draw_overlays(
+        &self,
+        image: &mut Mat,
+        status: Option<&str>,
+        alert: Option<String>,
+        overlay_texts: &[OverlayText],
+    ) -> VideoResult<()> {
+        let status_style = status_style();
+        let status_renderer = text_renderer::create_renderer(&status_style);
+        let mut lines = Vec::with_capacity(overlay_texts.len() + 2);
+        if let Some(status) = status {
+            lines.push(TextLine {
+                text: status.to_string(),
+                style: &status_style,
+                color: status_style.color,
+                renderer: status_renderer.as_ref(),
+            });
         }
-        current_index
+        if let Some(alert) = alert {
+            lines.push(TextLine {
+                text: alert,
+                style: &status_style,
+                color: ALERT_COLOR,
+                renderer: status_renderer.as_ref(),
+            });
+        }
+        if self.display_config.annotation.draws_overlays() {
+            lines.extend(overlay_texts.iter().map(OverlayText::line));
+        }
+
+        text_layout::draw_text_lines(image, &lines, |anchor| self.text_offset(anchor))
+    }
+
+    fn select_channel(&mut self) -> usize {
+        let channels: Vec<ChannelInfo> =
+            self.channels.iter().map(ChannelInfo::from_channel).collect();
+        let context = SelectionContext {
+            channels: &channels,
+            current_index: self.current_channel,
+        };
+
+        let mut next_index = self.channel_selector.select(&context);
+        if next_index >= self.channels.len() {
+            println!("Wrong camera index {}", next_index);
+            next_index = 0;
+        }
+
+        if next_index != self.current_channel {
+            println!(
+                "Changing camera to {} (index {})",
+                self.channels[next_index].name, next_index
+            );
+            self.current_channel = next_index;
+        }
+        next_index
+    }
+}
+
+fn status_style() -> OverlayStyle {
+    OverlayStyle {
+        anchor: TextPosition::TopLeft,
+        color: [255, 255, 0],
+        background: Some(BackgroundConfig::default()),
+        ..OverlayStyle::default()
     }
 }
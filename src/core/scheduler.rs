@@ -0,0 +1,257 @@
+use crate::core::shutdown;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Longest time a worker waits before checking whether it has to stop.
+const MAX_IDLE_WAIT: Duration = Duration::from_millis(100);
+
+/// Runs the periodic work of the overlay providers and of the recording retention on a
+/// small pool of threads, instead of a thread per service.
+pub(crate) struct Scheduler {
+    shared: Arc<SharedTasks>,
+    is_running: Arc<AtomicBool>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+struct SharedTasks {
+    tasks: Mutex<Vec<ScheduledTask>>,
+    /// Signalled when a task is added, so idle workers pick it up right away.
+    task_added: Condvar,
+}
+
+struct ScheduledTask {
+    name: String,
+    interval: Duration,
+    next_run: Instant,
+    /// The task is dropped once this flag is cleared.
+    is_active: Arc<AtomicBool>,
+    run: Box<dyn FnMut() + Send>,
+}
+
+impl Scheduler {
+    pub(crate) fn start(worker_count: usize) -> Self {
+        let shared = Arc::new(SharedTasks {
+            tasks: Mutex::new(Vec::new()),
+            task_added: Condvar::new(),
+        });
+        let is_running = Arc::new(AtomicBool::new(true));
+
+        let workers = (0..worker_count.max(1))
+            .map(|_| {
+                let shared = shared.clone();
+                let is_running = is_running.clone();
+                thread::spawn(move || run_worker(&shared, &is_running))
+            })
+            .collect();
+
+        Self {
+            shared,
+            is_running,
+            workers,
+        }
+    }
+
+    /// Runs the task right away, then every `interval` until `is_active` is cleared.
+    /// A run that takes longer than the interval delays the next one rather than
+    /// overlapping it.
+    pub(crate) fn schedule(
+        &self,
+        name: &str,
+        interval: Duration,
+        is_active: Arc<AtomicBool>,
+        run: impl FnMut() + Send + 'static,
+    ) {
+        let task = ScheduledTask {
+            name: name.to_string(),
+            interval,
+            next_run: Instant::now(),
+            is_active,
+            run: Box::new(run),
+        };
+        match self.shared.tasks.lock() {
+            Ok(mut tasks) => tasks.push(task),
+            Err(e) => {
+                println!("Could not schedule {}: {}", name, e);
+                return;
+            }
+        }
+        self.shared.task_added.notify_one();
+    }
+
+    /// Stops the workers and waits for them until the deadline. Returns false if some
+    /// worker was still running a task at the deadline.
+    pub(crate) fn join(&mut self, deadline: Instant) -> bool {
+        self.is_running.store(false, Ordering::Relaxed);
+        self.shared.task_added.notify_all();
+
+        let mut all_stopped = true;
+        for worker in self.workers.drain(..) {
+            all_stopped &= shutdown::join_until(worker, deadline, "scheduler worker");
+        }
+        all_stopped
+    }
+}
+
+/*
+A worker takes the task that is due out of the list while it runs it, so no other
+worker runs the same task at the same time, and puts it back with its next run time
+afterwards. Tasks that panic are logged and kept, so a provider that fails once in a
+while keeps being refreshed and can't stop the others.
+*/
+fn run_worker(shared: &SharedTasks, is_running: &AtomicBool) {
+    while is_running.load(Ordering::Relaxed) {
+        let Some(mut task) = wait_for_due_task(shared, is_running) else {
+            continue;
+        };
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| (task.run)()));
+        if result.is_err() {
+            println!("{} panicked, running it again in {:?}", task.name, task.interval);
+        }
+
+        task.next_run = Instant::max(task.next_run + task.interval, Instant::now());
+        match shared.tasks.lock() {
+            Ok(mut tasks) => tasks.push(task),
+            Err(e) => println!("Could not reschedule {}: {}", task.name, e),
+        }
+    }
+}
+
+/// Takes the first task that is due out of the list, waiting at most until the next
+/// one is due. Tasks that are no longer active are dropped.
+fn wait_for_due_task(shared: &SharedTasks, is_running: &AtomicBool) -> Option<ScheduledTask> {
+    let mut tasks = shared.tasks.lock().ok()?;
+    tasks.retain(|task| task.is_active.load(Ordering::Relaxed));
+
+    let now = Instant::now();
+    if let Some(position) = tasks.iter().position(|task| task.next_run <= now) {
+        return Some(tasks.swap_remove(position));
+    }
+
+    let wait = tasks
+        .iter()
+        .map(|task| task.next_run - now)
+        .min()
+        .unwrap_or(MAX_IDLE_WAIT)
+        .min(MAX_IDLE_WAIT);
+    if is_running.load(Ordering::Relaxed) {
+        let _ = shared.task_added.wait_timeout(tasks, wait);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    const INTERVAL: Duration = Duration::from_millis(5);
+
+    fn counting_task(counter: &Arc<AtomicUsize>) -> impl FnMut() + Send + 'static {
+        let counter = counter.clone();
+        move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn stop(mut scheduler: Scheduler) {
+        assert!(scheduler.join(Instant::now() + Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn panicking_task_doesnt_stop_the_others() {
+        let scheduler = Scheduler::start(1);
+        let is_active = Arc::new(AtomicBool::new(true));
+        let panics = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        let panic_counter = panics.clone();
+        scheduler.schedule("panicking task", INTERVAL, is_active.clone(), move || {
+            panic_counter.fetch_add(1, Ordering::Relaxed);
+            panic!("task failed");
+        });
+        scheduler.schedule(
+            "counting task",
+            INTERVAL,
+            is_active,
+            counting_task(&counter),
+        );
+        thread::sleep(Duration::from_millis(200));
+
+        assert!(panics.load(Ordering::Relaxed) >= 2);
+        assert!(counter.load(Ordering::Relaxed) >= 2);
+        stop(scheduler);
+    }
+
+    #[test]
+    fn task_never_runs_twice_at_the_same_time() {
+        let scheduler = Scheduler::start(4);
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+
+        let (task_running, task_max_running) = (running.clone(), max_running.clone());
+        scheduler.schedule(
+            "slow task",
+            Duration::ZERO,
+            Arc::new(AtomicBool::new(true)),
+            move || {
+                let now_running = task_running.fetch_add(1, Ordering::SeqCst) + 1;
+                task_max_running.fetch_max(now_running, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(10));
+                task_running.fetch_sub(1, Ordering::SeqCst);
+            },
+        );
+        thread::sleep(Duration::from_millis(200));
+
+        assert_eq!(max_running.load(Ordering::SeqCst), 1);
+        stop(scheduler);
+    }
+
+    #[test]
+    fn clearing_is_active_stops_the_task() {
+        let scheduler = Scheduler::start(2);
+        let is_active = Arc::new(AtomicBool::new(true));
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        scheduler.schedule("task", INTERVAL, is_active.clone(), counting_task(&counter));
+        thread::sleep(Duration::from_millis(100));
+        assert!(counter.load(Ordering::Relaxed) >= 2);
+
+        is_active.store(false, Ordering::Relaxed);
+        thread::sleep(Duration::from_millis(50));
+        let runs = counter.load(Ordering::Relaxed);
+        thread::sleep(Duration::from_millis(100));
+
+        assert_eq!(counter.load(Ordering::Relaxed), runs);
+        stop(scheduler);
+    }
+
+    #[test]
+    fn join_returns_at_the_deadline_when_a_task_is_stuck() {
+        let mut scheduler = Scheduler::start(1);
+        let started = Arc::new(AtomicBool::new(false));
+
+        let task_started = started.clone();
+        scheduler.schedule(
+            "stuck task",
+            INTERVAL,
+            Arc::new(AtomicBool::new(true)),
+            move || {
+                task_started.store(true, Ordering::Relaxed);
+                thread::sleep(Duration::from_secs(2));
+            },
+        );
+        while !started.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let join_start = Instant::now();
+        assert!(!scheduler.join(join_start + Duration::from_millis(50)));
+        assert!(join_start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,37 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const SLEEP_STEP: Duration = Duration::from_millis(100);
+const JOIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Sleeps for the given duration, returning early as soon as `is_running` is cleared,
+/// so that service threads don't delay the shutdown by a full polling interval.
+pub fn sleep_while_running(duration: Duration, is_running: &AtomicBool) {
+    let wake_up = Instant::now() + duration;
+    while is_running.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        if now >= wake_up {
+            break;
+        }
+        thread::sleep(SLEEP_STEP.min(wake_up - now));
+    }
+}
+
+/// Waits for the thread to finish until the deadline. Returns false if the thread was
+/// still running when the deadline was reached; the thread is then left detached.
+pub fn join_until(handle: JoinHandle<()>, deadline: Instant, thread_name: &str) -> bool {
+    while !handle.is_finished() {
+        if Instant::now() >= deadline {
+            println!("Timed out waiting for {} to stop", thread_name);
+            return false;
+        }
+        thread::sleep(JOIN_POLL_INTERVAL);
+    }
+
+    if handle.join().is_err() {
+        println!("{} stopped with a panic", thread_name);
+    }
+    true
+}
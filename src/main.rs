@@ -1,97 +1,174 @@
+mod channel_selectors;
+mod cli;
+mod config;
 mod core;
 mod overlay_text_providers;
 mod video_display;
 
+use crate::cli::{Cli, Command, RunArgs};
+use crate::config::ConfigWatcher;
 use crate::core::OmniPane;
-use crate::overlay_text_providers::{
-    OverlayTextProvider, TemperatureOverlayTextProvider, TimeOverlayTextProvider,
-};
-use crate::video_display::video_channel::{VideoChannel, VideoChannelSettings};
-use opencv::videoio::VideoCapture;
-use std::collections::VecDeque;
-use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use crate::overlay_text_providers::Overlay;
+use crate::video_display::key_bindings::KeyBindings;
+use crate::video_display::video_channel::VideoChannel;
+use crate::video_display::{devices, probe};
+use clap::Parser;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use std::{env, thread};
+use std::time::{Duration, Instant};
 
-fn main() {
-    println!("Starting video streaming...");
-
-    if let Some((url_list, mut list_of_overlay_providers)) = read_arguments() {
-        let mut channels: Vec<VideoChannel> = Vec::new();
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+/// Exit code used when some service didn't stop cleanly.
+const EXIT_SHUTDOWN_TIMEOUT: u8 = 2;
 
-        for url in url_list {
-            // TODO: error handling
-            let camera = VideoCapture::from_file(url.as_str(), opencv::videoio::CAP_ANY).unwrap();
-            channels.push(VideoChannel::new(camera, VideoChannelSettings::default()));
-            println!("Added camera for url {}", url.as_str());
-        }
+fn main() -> ExitCode {
+    let cli = Cli::parse();
 
-        // TODO: create a proper service stop mechanism
-        let running = Arc::new(AtomicBool::new(true));
+    match cli.command {
+        Command::Run(args) => run(args),
+        Command::Probe { url } => probe(&url),
+        Command::ListDevices => list_devices(),
+        Command::Validate { config } => validate(&config),
+    }
+}
 
-        for text_provider in &mut list_of_overlay_providers {
-            text_provider.start_service(running.clone());
+fn run(args: RunArgs) -> ExitCode {
+    let (config, config_path) = match args.load_config() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
         }
+    };
 
-        let n_channels = channels.len() as u8;
-        let mut streamer = OmniPane::new(channels, list_of_overlay_providers);
-        let camera_index = streamer.current_camera_index.clone();
+    println!("Starting video streaming...");
 
-        camera_switcher(camera_index, n_channels, running.clone());
+    let mut channels: Vec<VideoChannel> = Vec::new();
+
+    for channel_config in &config.channels {
+        channels.push(VideoChannel::open(
+            channel_config,
+            &config.recording,
+            &config.snapshots,
+        ));
+        println!(
+            "Added camera {} for url {}",
+            channel_config.name, channel_config.url
+        );
+    }
 
-        streamer.start_display(running.clone());
+    let overlays: Vec<Overlay> = config
+        .overlays
+        .iter()
+        .map(overlay_text_providers::create_overlay)
+        .collect();
 
-        // TODO: improve services exit sync
-        running.store(false, Ordering::Relaxed);
-    } else {
-        panic!("No video stream specified!");
+    let running = Arc::new(AtomicBool::new(true));
+    if let Err(e) = set_signal_handler(running.clone()) {
+        eprintln!("Could not set the signal handler: {}", e);
     }
-}
 
-fn read_arguments() -> Option<(Vec<String>, Vec<Box<dyn OverlayTextProvider>>)> {
-    let mut args: VecDeque<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: {} \"URL\"", args[0]);
-        return None;
+    let mut streamer = OmniPane::new(
+        channels,
+        overlays,
+        channel_selectors::create_selector(&config),
+        KeyBindings::from_config(&config.keys),
+        config.display.clone(),
+        config.recording.clone(),
+        config.snapshots.clone(),
+    );
+
+    if let Some(config_path) = config_path {
+        let mut config_watcher = ConfigWatcher::new(config_path, CONFIG_POLL_INTERVAL);
+        config_watcher.start(running.clone());
+        streamer.watch_config(config_watcher);
     }
 
-    // executable's path
-    let _ = args.pop_front();
-
-    let mut url_list: Vec<String> = vec![];
-    let mut list_of_overlay_providers: Vec<Box<dyn OverlayTextProvider>> = Vec::new();
-
-    while !args.is_empty() {
-        let parameter = args.pop_front().unwrap();
-        if parameter.eq_ignore_ascii_case("--overlay:time") {
-            list_of_overlay_providers.push(Box::new(TimeOverlayTextProvider::new()));
-        } else if parameter.starts_with("--overlay:temperature=") {
-            // TODO: make a better argument parsing mechanism
-            let sensor_id = parameter.split("--overlay:temperature=").last().unwrap();
-            list_of_overlay_providers
-                .push(Box::new(TemperatureOverlayTextProvider::new(sensor_id)));
+    let display_result = streamer.start_display(running.clone());
+
+    println!("Stopping video streaming...");
+    running.store(false, Ordering::Relaxed);
+
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+    let all_stopped = streamer.shutdown(deadline);
+
+    if let Err(e) = display_result {
+        eprintln!("{}", e);
+        return ExitCode::FAILURE;
+    }
+    if !all_stopped {
+        return ExitCode::from(EXIT_SHUTDOWN_TIMEOUT);
+    }
+    ExitCode::SUCCESS
+}
+
+/// Stops the display on SIGINT and SIGTERM. A second signal exits right away, in case
+/// the clean shutdown is stuck.
+fn set_signal_handler(running: Arc<AtomicBool>) -> Result<(), ctrlc::Error> {
+    ctrlc::set_handler(move || {
+        if running.swap(false, Ordering::Relaxed) {
+            println!("Stop signal received");
         } else {
-            url_list.push(parameter);
+            eprintln!("Second stop signal received, exiting without cleanup");
+            std::process::exit(EXIT_SHUTDOWN_TIMEOUT as i32);
+        }
+    })
+}
+
+fn probe(url: &str) -> ExitCode {
+    match probe::probe_stream(url) {
+        Ok(properties) => {
+            println!("{}", properties);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
         }
     }
+}
 
-    if !url_list.is_empty() {
-        Some((url_list, list_of_overlay_providers))
-    } else {
-        None
+fn list_devices() -> ExitCode {
+    match devices::list_capture_devices() {
+        Ok(devices) if devices.is_empty() => {
+            println!("No capture devices found");
+            ExitCode::SUCCESS
+        }
+        Ok(devices) => {
+            for device in devices {
+                println!(
+                    "{}\t{} (index {})",
+                    device.path.display(),
+                    device.name,
+                    device.index
+                );
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Could not list capture devices: {}", e);
+            ExitCode::FAILURE
+        }
     }
 }
 
-// TODO: create a proper channel selector mechanism
-fn camera_switcher(camera_index: Arc<AtomicU8>, list_size: u8, running: Arc<AtomicBool>) {
-    thread::spawn(move || {
-        while running.load(Ordering::Relaxed) {
-            thread::sleep(Duration::from_secs(10));
-            let mut current_index = camera_index.load(Ordering::Relaxed);
-            current_index = (current_index + 1) % list_size;
-            println!("Changing camera to index {}", current_index);
-            camera_index.store(current_index, Ordering::Relaxed);
+fn validate(config_path: &Path) -> ExitCode {
+    match config::load_config(config_path) {
+        Ok(config) => {
+            println!(
+                "{} is valid: {} channel(s), {} overlay(s)",
+                config_path.display(),
+                config.channels.len(),
+                config.overlays.len()
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
         }
-    });
+    }
 }
@@ -1,13 +1,32 @@
-use std::sync::atomic::{AtomicBool, Ordering};
+use crate::core::scheduler::Scheduler;
+use inotify::{Inotify, WatchMask};
+use std::ffi::{CString, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use std::{fs, thread};
+use std::{fs, io};
+
+/// How often watched files are checked for change events.
+const EVENT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+const INOTIFY_BUFFER_SIZE: usize = 4096;
 
 pub(crate) struct FilePoller {
     filepath: String,
     poll_interval: Duration,
-    current_file_content: Arc<Mutex<String>>,
-    last_update: Arc<Mutex<Option<Instant>>>,
+    polled_file: Arc<Mutex<PolledFile>>,
+}
+
+/// Result of the reads of a polled file.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PolledFile {
+    /// Content of the last successful read.
+    pub(crate) content: String,
+    /// When the file was last read successfully, None if it never was.
+    pub(crate) last_update: Option<Instant>,
+    /// Error of the last read, cleared by the next successful one.
+    pub(crate) error: Option<String>,
 }
 
 impl FilePoller {
@@ -15,42 +34,176 @@ impl FilePoller {
         Self {
             filepath,
             poll_interval,
-            current_file_content: Arc::new(Mutex::new(String::new())),
-            last_update: Arc::new(Mutex::new(None)),
+            polled_file: Arc::new(Mutex::new(PolledFile::default())),
         }
     }
 
-    pub fn start(&mut self, is_running: Arc<AtomicBool>) {
-        let poll_interval = self.poll_interval.clone();
+    /// Reads the file whenever it changes, or every poll interval when it is on a
+    /// filesystem that doesn't send change events, like sysfs and procfs. The reads run
+    /// on the scheduler until `is_running` is cleared.
+    pub fn start(&mut self, scheduler: &Scheduler, is_running: Arc<AtomicBool>) {
+        let poll_interval = self.poll_interval;
         let filepath = self.filepath.clone();
-        let current_file_content = self.current_file_content.clone();
-        let last_update = self.last_update.clone();
-
-        // TODO: deal with thread handler
-        let _ = thread::spawn(move || {
-            while is_running.load(Ordering::Relaxed) {
-                // TODO: error handling
-                match current_file_content.lock() {
-                    Ok(mut content) => {
-                        *content = fs::read_to_string(&filepath).unwrap();
-                        match last_update.lock() {
-                            Ok(mut last_update) => {
-                                *last_update = Some(Instant::now());
-                            }
-                            Err(_) => {}
-                        }
-                    }
+        let polled_file = self.polled_file.clone();
+
+        let mut watch = FileWatch::new(Path::new(&filepath));
+        let check_interval = match watch {
+            Some(_) => EVENT_CHECK_INTERVAL,
+            None => poll_interval,
+        };
+        let mut last_read: Option<Instant> = None;
+
+        let task_name = format!("poller of {}", filepath);
+        scheduler.schedule(&task_name, check_interval, is_running, move || {
+            let read_due = match &mut watch {
+                Some(file_watch) => match file_watch.has_changed() {
+                    Ok(changed) => changed || last_read.is_none(),
                     Err(e) => {
-                        println!("File Poller Error: {:?}", e);
+                        println!(
+                            "Stopped watching {} for changes: {}, polling it instead",
+                            filepath, e
+                        );
+                        watch = None;
+                        true
                     }
-                }
-                thread::sleep(poll_interval);
+                },
+                /*
+                Polled files are read on every run. A watch that failed keeps the
+                short interval of its task, so the file is only read once the poll
+                interval has passed.
+                */
+                None => last_read.is_none_or(|last_read| {
+                    last_read.elapsed() + check_interval > poll_interval
+                }),
+            };
+            if read_due {
+                read_file(&filepath, &polled_file);
+                last_read = Some(Instant::now());
             }
         });
     }
 
-    pub fn get_current_file_content(&self) -> String {
-        // TODO: error handling
-        self.current_file_content.lock().unwrap().clone()
+    pub fn get_current_file(&self) -> PolledFile {
+        match self.polled_file.lock() {
+            Ok(polled_file) => polled_file.clone(),
+            Err(e) => PolledFile {
+                error: Some(e.to_string()),
+                ..PolledFile::default()
+            },
+        }
+    }
+}
+
+/// inotify watch on the directory of a file. The directory is watched rather than the
+/// file, so files that are replaced by a rename or created later are still seen.
+struct FileWatch {
+    inotify: Inotify,
+    file_name: OsString,
+    buffer: Vec<u8>,
+}
+
+impl FileWatch {
+    /// Returns None when the file can't be watched, so it has to be polled.
+    fn new(filepath: &Path) -> Option<Self> {
+        let directory = match filepath.parent() {
+            Some(directory) if !directory.as_os_str().is_empty() => directory,
+            _ => Path::new("."),
+        };
+        let file_name = filepath.file_name()?.to_os_string();
+        if !sends_change_events(directory) {
+            return None;
+        }
+
+        let watch = Inotify::init().and_then(|inotify| {
+            inotify.watches().add(
+                directory,
+                WatchMask::CLOSE_WRITE
+                    | WatchMask::MODIFY
+                    | WatchMask::CREATE
+                    | WatchMask::DELETE
+                    | WatchMask::MOVED_TO
+                    | WatchMask::MOVED_FROM,
+            )?;
+            Ok(inotify)
+        });
+        match watch {
+            Ok(inotify) => Some(Self {
+                inotify,
+                file_name,
+                buffer: vec![0; INOTIFY_BUFFER_SIZE],
+            }),
+            Err(e) => {
+                println!(
+                    "Could not watch {} for changes: {}, polling it instead",
+                    filepath.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Tells whether events about the file were received since the last call, without
+    /// waiting for them.
+    fn has_changed(&mut self) -> io::Result<bool> {
+        match self.inotify.read_events(&mut self.buffer) {
+            Ok(mut events) => {
+                Ok(events.any(|event| event.name == Some(self.file_name.as_os_str())))
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/*
+Files of sysfs and procfs are generated when they are read, so their content changes
+without inotify ever sending an event. Paths whose filesystem can't be checked are
+polled as well.
+*/
+fn sends_change_events(directory: &Path) -> bool {
+    let Ok(path) = CString::new(directory.as_os_str().as_bytes()) else {
+        return false;
+    };
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return false;
+    }
+    stat.f_type != libc::SYSFS_MAGIC && stat.f_type != libc::PROC_SUPER_MAGIC
+}
+
+fn read_file(filepath: &str, polled_file: &Mutex<PolledFile>) {
+    let read_result = fs::read_to_string(filepath);
+    match polled_file.lock() {
+        Ok(mut polled_file) => update_polled_file(&mut polled_file, filepath, read_result),
+        Err(e) => {
+            println!("File Poller Error: {:?}", e);
+        }
+    }
+}
+
+/// Keeps the last content when the read failed, so it can still be shown with its age.
+/// Errors are logged when they first happen instead of on every poll.
+fn update_polled_file(
+    polled_file: &mut PolledFile,
+    filepath: &str,
+    read_result: io::Result<String>,
+) {
+    match read_result {
+        Ok(content) => {
+            if polled_file.error.is_some() {
+                println!("Reading {} again", filepath);
+            }
+            polled_file.content = content;
+            polled_file.last_update = Some(Instant::now());
+            polled_file.error = None;
+        }
+        Err(e) => {
+            let error = e.to_string();
+            if polled_file.error.as_ref() != Some(&error) {
+                println!("Could not read {}: {}", filepath, error);
+            }
+            polled_file.error = Some(error);
+        }
     }
 }
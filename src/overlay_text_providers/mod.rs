@@ -2,13 +2,132 @@ pub(crate) mod time_provider;
 pub(crate) mod file_polling;
 pub(crate) mod temperature_provider;
 
+use crate::config::{OverlayConfig, OverlayStyle};
+use crate::core::scheduler::Scheduler;
+use crate::video_display::text_renderer;
+use crate::video_display::text_renderer::TextRenderer;
 use std::sync::Arc;
 use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
 pub use time_provider::TimeOverlayTextProvider;
 pub use temperature_provider::TemperatureOverlayTextProvider;
 
+/// Age and error state of the data shown by a provider.
+#[derive(Debug, Clone, Default)]
+pub struct DataStatus {
+    /// When the data was last updated, None if it never was.
+    pub last_update: Option<Instant>,
+    /// Why the data couldn't be updated the last time it was tried.
+    pub error: Option<String>,
+}
+
+/// Marker added to the text of an overlay whose data can't be trusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataMarker {
+    Stale,
+    Error,
+}
+
+impl DataMarker {
+    pub fn text(&self) -> &'static str {
+        match self {
+            DataMarker::Stale => "(stale)",
+            DataMarker::Error => "(error)",
+        }
+    }
+}
+
 pub trait OverlayTextProvider {
     fn get_text(&self) -> String;
 
-    fn start_service(&mut self, _is_running: Arc<AtomicBool>) { }
-}
\ No newline at end of file
+    /// State of the data behind each line of the text, in the same order. None for
+    /// providers that don't read it from somewhere else, like the clock.
+    fn data_statuses(&self) -> Option<Vec<DataStatus>> {
+        None
+    }
+
+    /// Schedules the work that keeps the text up to date, which runs until
+    /// `is_running` is cleared.
+    fn start_service(&mut self, _scheduler: &Scheduler, _is_running: Arc<AtomicBool>) { }
+}
+
+/// Provider together with the style its text is drawn with.
+pub struct Overlay {
+    pub provider: Box<dyn OverlayTextProvider>,
+    pub style: OverlayStyle,
+    pub(crate) renderer: Arc<dyn TextRenderer>,
+    /// Age after which the data of the provider is shown as stale.
+    pub max_age: Option<Duration>,
+}
+
+impl Overlay {
+    /// Lines of the text, each with the marker of its own data, so that one failing
+    /// reading doesn't mark the others.
+    pub fn marked_lines(&self) -> Vec<(String, Option<DataMarker>)> {
+        let statuses = self.provider.data_statuses();
+        self.provider
+            .get_text()
+            .lines()
+            .enumerate()
+            .map(|(index, line)| {
+                let marker = statuses
+                    .as_ref()
+                    .and_then(|statuses| statuses.get(index))
+                    .and_then(|status| self.data_marker(status));
+                (line.to_string(), marker)
+            })
+            .collect()
+    }
+
+    /// Tells whether a line has to be marked as stale or in error. Data that wasn't
+    /// read yet is not marked, as long as reading it didn't fail.
+    fn data_marker(&self, status: &DataStatus) -> Option<DataMarker> {
+        if status.error.is_some() {
+            return Some(DataMarker::Error);
+        }
+        match (status.last_update, self.max_age) {
+            (Some(last_update), Some(max_age)) if last_update.elapsed() > max_age => {
+                Some(DataMarker::Stale)
+            }
+            _ => None,
+        }
+    }
+}
+
+pub fn create_overlay(config: &OverlayConfig) -> Overlay {
+    Overlay {
+        provider: create_provider(config),
+        style: config.style().clone(),
+        renderer: Arc::from(text_renderer::create_renderer(config.style())),
+        max_age: config.max_age(),
+    }
+}
+
+pub fn create_provider(config: &OverlayConfig) -> Box<dyn OverlayTextProvider> {
+    match config {
+        OverlayConfig::Time {
+            format,
+            timezone,
+            label,
+            ..
+        } => Box::new(TimeOverlayTextProvider::new(
+            format,
+            *timezone,
+            label.as_deref(),
+        )),
+        OverlayConfig::Temperature {
+            sensor_id,
+            unit,
+            label,
+            offset,
+            sysfs_root,
+            ..
+        } => Box::new(TemperatureOverlayTextProvider::new(
+            sensor_id.as_deref(),
+            *unit,
+            label.as_deref(),
+            *offset,
+            sysfs_root,
+        )),
+    }
+}
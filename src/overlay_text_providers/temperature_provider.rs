@@ -1,50 +1,293 @@
+use crate::config::TemperatureUnit;
+use crate::core::scheduler::Scheduler;
 use crate::overlay_text_providers::file_polling::FilePoller;
-use crate::overlay_text_providers::OverlayTextProvider;
+use crate::overlay_text_providers::{DataStatus, OverlayTextProvider};
+use std::fs;
+use std::path::Path;
 use std::sync::atomic::AtomicBool;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+/// Prefix of the ids of DS18B20 sensors.
+const DS18B20_FAMILY_PREFIX: &str = "28-";
+const DEFAULT_LABEL: &str = "Temperature";
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Shows the temperature of one or more 1-Wire sensors, one line per sensor.
 pub struct TemperatureOverlayTextProvider {
+    sensors: Vec<TemperatureSensor>,
+    unit: TemperatureUnit,
+    offset: f64,
+}
+
+struct TemperatureSensor {
+    label: String,
     file_poller: FilePoller,
+    last_valid_reading: Mutex<Option<Reading>>,
+}
+
+/// Reading that passed the CRC check.
+#[derive(Clone, Copy)]
+struct Reading {
+    celsius: f64,
+    /// When the file was read.
+    time: Instant,
 }
 
 impl TemperatureOverlayTextProvider {
-    pub fn new(sensor_id: &str) -> Self {
-        let sensor_file_path = format!("/sys/bus/w1/devices/{}/w1_slave", sensor_id);
-        let poll_interval = Duration::from_secs(5);
+    /// Reads the given sensor, or every DS18B20 sensor found under `sysfs_root` when
+    /// no sensor id is given.
+    pub fn new(
+        sensor_id: Option<&str>,
+        unit: TemperatureUnit,
+        label: Option<&str>,
+        offset: f64,
+        sysfs_root: &Path,
+    ) -> Self {
+        let sensors = match sensor_id {
+            Some(sensor_id) => vec![TemperatureSensor::new(
+                sysfs_root,
+                sensor_id,
+                label.unwrap_or(DEFAULT_LABEL).to_string(),
+            )],
+            None => discover_sensors(sysfs_root)
+                .into_iter()
+                .map(|sensor_id| {
+                    let sensor_label = match label {
+                        Some(label) => format!("{} {}", label, sensor_id),
+                        None => sensor_id.clone(),
+                    };
+                    TemperatureSensor::new(sysfs_root, &sensor_id, sensor_label)
+                })
+                .collect(),
+        };
+
+        if sensor_id.is_none() {
+            println!(
+                "Found {} temperature sensor(s) in {}",
+                sensors.len(),
+                sysfs_root.display()
+            );
+        }
 
         Self {
-            file_poller: FilePoller::new(sensor_file_path, poll_interval),
+            sensors,
+            unit,
+            offset,
         }
     }
 
     pub fn get_temperature_text(&self) -> String {
-        match self
-            .file_poller
-            .get_current_file_content()
-            .split("t=")
-            .last()
-        {
-            Some(value) => match value.trim().to_string().parse::<f32>() {
-                Ok(raw_temp) => {
-                    let temperature = raw_temp / 1000.0;
-                    format!("Temperature: {:.1} C", temperature)
-                }
-                Err(_) => {
-                    format!("Error parsing temperature {}", value)
-                }
-            },
-            None => "No temperature found".to_string(),
+        if self.sensors.is_empty() {
+            return "No temperature sensors found".to_string();
         }
+
+        self.sensors
+            .iter()
+            .map(|sensor| match sensor.read().0 {
+                Some(reading) => format!(
+                    "{}: {:.1} {}",
+                    sensor.label,
+                    self.unit.convert_celsius(reading.celsius + self.offset),
+                    self.unit.symbol()
+                ),
+                None => format!("{}: no valid reading", sensor.label),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
     }
 }
 
+impl TemperatureSensor {
+    fn new(sysfs_root: &Path, sensor_id: &str, label: String) -> Self {
+        let sensor_file_path = sysfs_root.join(sensor_id).join("w1_slave");
+
+        Self {
+            label,
+            file_poller: FilePoller::new(sensor_file_path.display().to_string(), POLL_INTERVAL),
+            last_valid_reading: Mutex::new(None),
+        }
+    }
+
+    /*
+    A reading that failed its CRC check or couldn't be parsed is no new reading: the
+    last valid one is kept, and shows as stale once it is too old. Only a file that
+    can't be read, or a sensor that never gave a valid reading, is an error.
+    */
+    fn read(&self) -> (Option<Reading>, Option<String>) {
+        let polled_file = self.file_poller.get_current_file();
+        let Ok(mut last_valid_reading) = self.last_valid_reading.lock() else {
+            return (None, Some("lock poisoned".to_string()));
+        };
+        if let Some(time) = polled_file.last_update {
+            if let Some(celsius) = parse_w1_slave(&polled_file.content) {
+                *last_valid_reading = Some(Reading { celsius, time });
+            }
+        }
+
+        let error = polled_file.error.or_else(|| {
+            let never_valid = polled_file.last_update.is_some() && last_valid_reading.is_none();
+            never_valid.then(|| "no valid reading".to_string())
+        });
+        (*last_valid_reading, error)
+    }
+
+    fn data_status(&self) -> DataStatus {
+        let (reading, error) = self.read();
+        DataStatus {
+            last_update: reading.map(|reading| reading.time),
+            error,
+        }
+    }
+}
+
+/*
+The w1_slave file of a DS18B20 has two lines: the bytes read from the sensor
+followed by the result of the CRC check, then the same bytes followed by the
+temperature in thousandths of a degree Celsius:
+
+    72 01 4b 46 7f ff 0e 10 57 : crc=57 YES
+    72 01 4b 46 7f ff 0e 10 57 t=23125
+
+Readings whose CRC check didn't end with "YES" are discarded.
+*/
+fn parse_w1_slave(content: &str) -> Option<f64> {
+    let mut lines = content.lines();
+    let crc_line = lines.next()?;
+    if !crc_line.trim_end().ends_with("YES") {
+        return None;
+    }
+
+    let (_, raw_temperature) = lines.next()?.split_once("t=")?;
+    let millidegrees: i32 = raw_temperature.trim().parse().ok()?;
+    Some(millidegrees as f64 / 1000.0)
+}
+
+/// Ids of the DS18B20 sensors found in the 1-Wire sysfs directory, sorted.
+fn discover_sensors(sysfs_root: &Path) -> Vec<String> {
+    let entries = match fs::read_dir(sysfs_root) {
+        Ok(entries) => entries,
+        Err(e) => {
+            println!(
+                "Could not list temperature sensors in {}: {}",
+                sysfs_root.display(),
+                e
+            );
+            return Vec::new();
+        }
+    };
+
+    let mut sensor_ids: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.starts_with(DS18B20_FAMILY_PREFIX))
+        .collect();
+    sensor_ids.sort();
+    sensor_ids
+}
+
 impl OverlayTextProvider for TemperatureOverlayTextProvider {
     fn get_text(&self) -> String {
         self.get_temperature_text()
     }
 
-    fn start_service(&mut self, is_running: Arc<AtomicBool>) {
-        self.file_poller.start(is_running);
+    /// The state of every sensor, one per line. The line telling that no sensor was
+    /// found is an error.
+    fn data_statuses(&self) -> Option<Vec<DataStatus>> {
+        if self.sensors.is_empty() {
+            return Some(vec![DataStatus {
+                last_update: None,
+                error: Some("no sensors found".to_string()),
+            }]);
+        }
+        let statuses = self.sensors.iter().map(TemperatureSensor::data_status);
+        Some(statuses.collect())
+    }
+
+    fn start_service(&mut self, scheduler: &Scheduler, is_running: Arc<AtomicBool>) {
+        for sensor in &mut self.sensors {
+            sensor.file_poller.start(scheduler, is_running.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Empty directory for a test, removed when the test is done.
+    struct TestDirectory(PathBuf);
+
+    impl TestDirectory {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("omni_pane_{}_{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TestDirectory {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn parse_w1_slave_reads_temperature_when_crc_is_valid() {
+        let content = "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n\
+                       72 01 4b 46 7f ff 0e 10 57 t=23125\n";
+        assert_eq!(parse_w1_slave(content), Some(23.125));
+    }
+
+    #[test]
+    fn parse_w1_slave_rejects_failed_crc() {
+        let content = "72 01 4b 46 7f ff 0e 10 57 : crc=57 NO\n\
+                       72 01 4b 46 7f ff 0e 10 57 t=23125\n";
+        assert_eq!(parse_w1_slave(content), None);
+    }
+
+    #[test]
+    fn parse_w1_slave_rejects_missing_temperature() {
+        let content = "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n\
+                       72 01 4b 46 7f ff 0e 10 57\n";
+        assert_eq!(parse_w1_slave(content), None);
+        assert_eq!(
+            parse_w1_slave("72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_w1_slave_reads_negative_temperature() {
+        let content = "5e ff 4b 46 7f ff 02 10 1f : crc=1f YES\n\
+                       5e ff 4b 46 7f ff 02 10 1f t=-10125\n";
+        assert_eq!(parse_w1_slave(content), Some(-10.125));
+    }
+
+    #[test]
+    fn discover_sensors_finds_only_ds18b20_sensors() {
+        let directory = TestDirectory::new("discover_sensors");
+        for name in [
+            "28-000000000002",
+            "28-000000000001",
+            "w1_bus_master1",
+            "10-000000000003",
+        ] {
+            fs::create_dir(directory.0.join(name)).unwrap();
+        }
+
+        assert_eq!(
+            discover_sensors(&directory.0),
+            vec!["28-000000000001", "28-000000000002"]
+        );
+    }
+
+    #[test]
+    fn discover_sensors_returns_nothing_for_missing_directory() {
+        let directory = TestDirectory::new("discover_missing");
+        assert!(discover_sensors(&directory.0.join("missing")).is_empty());
     }
 }
@@ -1,21 +1,38 @@
+use crate::config::TimeZoneConfig;
 use crate::overlay_text_providers::OverlayTextProvider;
-use chrono::Utc;
+use chrono::{Local, Utc};
 
-pub struct TimeOverlayTextProvider {}
+pub struct TimeOverlayTextProvider {
+    format: String,
+    timezone: TimeZoneConfig,
+    label: Option<String>,
+}
 
 impl TimeOverlayTextProvider {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(format: &str, timezone: TimeZoneConfig, label: Option<&str>) -> Self {
+        Self {
+            format: format.to_string(),
+            timezone,
+            label: label.map(str::to_string),
+        }
     }
-    
+
     fn get_current_time(&self) -> String {
-        Utc::now()
-            .format("%d/%m/%Y %H:%M:%S")
-            .to_string()
+        let time = match self.timezone {
+            TimeZoneConfig::Local => Local::now().format(&self.format).to_string(),
+            TimeZoneConfig::Named(timezone) => Utc::now()
+                .with_timezone(&timezone)
+                .format(&self.format)
+                .to_string(),
+        };
+
+        match &self.label {
+            Some(label) => format!("{} {}", label, time),
+            None => time,
+        }
     }
 }
 
-
 impl OverlayTextProvider for TimeOverlayTextProvider {
     fn get_text(&self) -> String {
         self.get_current_time()
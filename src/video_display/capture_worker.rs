@@ -0,0 +1,515 @@
+use crate::config::FrameAnnotation;
+use crate::core::shutdown;
+use crate::video_display::motion_detection::{MotionDetector, MotionState};
+use crate::video_display::reconnection::Backoff;
+use crate::video_display::recording::{
+    ChannelRecordingSettings, ClosingFiles, MotionRecorder, SegmentRecorder, TimelapseRecorder,
+};
+use crate::video_display::snapshot;
+use crate::video_display::snapshot::{ChannelSnapshotSettings, Snapshotter};
+use crate::video_display::text_layout;
+use crate::video_display::text_layout::OverlayText;
+use crate::video_display::video_channel::{ChannelState, ChannelStatus, VideoChannelSettings};
+use crate::video_display::{ImageFrame, VideoResult, VideoStreamError};
+use opencv::core::{Mat, MatTraitConst};
+use opencv::hub_prelude::{VideoCaptureTrait, VideoCaptureTraitConst};
+use opencv::videoio::{VideoCapture, CAP_ANY, CAP_PROP_BUFFERSIZE, CAP_PROP_FPS};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+/// Highest frame rate reported by a stream that is believed. Some backends report the
+/// clock rate of the stream, e.g. 90000 for RTSP, instead of its frame rate.
+const MAX_STREAM_FPS: f64 = 240.0;
+/// Weight of the latest frame interval in the measured frame rate.
+const FRAME_RATE_SMOOTHING: f64 = 0.1;
+
+/// State shared between a `VideoChannel` and its capture thread.
+pub(crate) struct ChannelShared {
+    settings: Mutex<VideoChannelSettings>,
+    status: Mutex<ChannelStatus>,
+    latest_frame: Mutex<Option<ImageFrame>>,
+    motion: Mutex<MotionState>,
+    recording: Mutex<ChannelRecordingSettings>,
+    snapshots: Mutex<ChannelSnapshotSettings>,
+    /// Text drawn over the recordings and snapshots that are composited, updated by the
+    /// display.
+    overlay_texts: Mutex<Vec<OverlayText>>,
+}
+
+impl ChannelShared {
+    pub fn new(
+        settings: VideoChannelSettings,
+        recording: ChannelRecordingSettings,
+        snapshots: ChannelSnapshotSettings,
+    ) -> Self {
+        Self {
+            settings: Mutex::new(settings),
+            status: Mutex::new(ChannelStatus::default()),
+            latest_frame: Mutex::new(None),
+            motion: Mutex::new(MotionState::default()),
+            recording: Mutex::new(recording),
+            snapshots: Mutex::new(snapshots),
+            overlay_texts: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn settings(&self) -> VideoChannelSettings {
+        *lock(&self.settings)
+    }
+
+    pub fn set_settings(&self, settings: VideoChannelSettings) {
+        *lock(&self.settings) = settings;
+    }
+
+    pub fn status(&self) -> MutexGuard<'_, ChannelStatus> {
+        lock(&self.status)
+    }
+
+    pub fn latest_frame(&self) -> Option<ImageFrame> {
+        lock(&self.latest_frame).clone()
+    }
+
+    fn set_latest_frame(&self, frame: Option<ImageFrame>) {
+        *lock(&self.latest_frame) = frame;
+    }
+
+    pub fn motion(&self) -> MotionState {
+        lock(&self.motion).clone()
+    }
+
+    fn set_motion(&self, motion: MotionState) {
+        *lock(&self.motion) = motion;
+    }
+
+    fn recording(&self) -> ChannelRecordingSettings {
+        lock(&self.recording).clone()
+    }
+
+    pub fn set_recording(&self, recording: ChannelRecordingSettings) {
+        *lock(&self.recording) = recording;
+    }
+
+    fn snapshots(&self) -> ChannelSnapshotSettings {
+        lock(&self.snapshots).clone()
+    }
+
+    pub fn set_snapshots(&self, snapshots: ChannelSnapshotSettings) {
+        *lock(&self.snapshots) = snapshots;
+    }
+
+    fn overlay_texts(&self) -> Vec<OverlayText> {
+        lock(&self.overlay_texts).clone()
+    }
+
+    pub fn set_overlay_texts(&self, overlay_texts: Vec<OverlayText>) {
+        *lock(&self.overlay_texts) = overlay_texts;
+    }
+}
+
+/*
+A panic in one of the threads must not prevent the others from using the shared
+state, the data itself is always left consistent.
+*/
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Reads the stream of a channel on its own thread, so the latest frame is always
+/// available without waiting for I/O, and reopens the stream when it is lost.
+/// Movement is detected, recorded and snapshotted here as well, so it is known for
+/// channels that aren't displayed.
+struct CaptureWorker {
+    name: String,
+    url: String,
+    shared: Arc<ChannelShared>,
+    camera: Option<VideoCapture>,
+    /// Frame rate reported by the stream when it was opened, None when it reports none.
+    stream_fps: Option<f64>,
+    frame_rate: FrameRateMeter,
+    backoff: Backoff,
+    motion_detector: MotionDetector,
+    motion_recorder: Option<MotionRecorder>,
+    segment_recorder: Option<SegmentRecorder>,
+    timelapse_recorder: Option<TimelapseRecorder>,
+    closing_files: ClosingFiles,
+    recording_annotation: FrameAnnotation,
+    snapshotter: Snapshotter,
+}
+
+pub(crate) fn start(
+    name: String,
+    url: String,
+    shared: Arc<ChannelShared>,
+    is_running: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let settings = shared.settings();
+        let snapshotter = Snapshotter::new(shared.snapshots());
+        let mut worker = CaptureWorker {
+            name,
+            url,
+            shared,
+            camera: None,
+            stream_fps: None,
+            frame_rate: FrameRateMeter::default(),
+            backoff: Backoff::new(settings.reconnect_initial_delay, settings.reconnect_max_delay),
+            motion_detector: MotionDetector::new(),
+            motion_recorder: None,
+            segment_recorder: None,
+            timelapse_recorder: None,
+            closing_files: ClosingFiles::default(),
+            recording_annotation: FrameAnnotation::Raw,
+            snapshotter,
+        };
+        worker.run(&is_running);
+    })
+}
+
+impl CaptureWorker {
+    fn run(&mut self, is_running: &AtomicBool) {
+        while is_running.load(Ordering::Relaxed) {
+            let settings = self.shared.settings();
+            self.backoff
+                .set_delays(settings.reconnect_initial_delay, settings.reconnect_max_delay);
+            self.update_recorders();
+            self.update_snapshotter();
+
+            if self.camera.is_none() {
+                if self.backoff.is_due() {
+                    self.connect(&settings);
+                }
+                if self.camera.is_none() {
+                    shutdown::sleep_while_running(self.backoff.time_until_due(), is_running);
+                    continue;
+                }
+            }
+
+            let read_start = Instant::now();
+            if let Some(image) = self.read_frame() {
+                let frame = ImageFrame {
+                    image,
+                    instant: Instant::now(),
+                };
+                self.frame_rate.add_frame(frame.instant);
+                let checked_motion = self.detect_motion(&frame.image, &settings);
+                self.record(&frame, &settings);
+                self.take_snapshots(&frame, checked_motion.as_ref());
+                self.shared.set_latest_frame(Some(frame));
+            }
+
+            // Don't read faster than the configured frame rate, e.g. when playing files
+            let read_duration = read_start.elapsed();
+            if read_duration < settings.frame_duration {
+                shutdown::sleep_while_running(settings.frame_duration - read_duration, is_running);
+            }
+        }
+
+        self.stop_recorders();
+        if let Some(timelapse_recorder) = &mut self.timelapse_recorder {
+            timelapse_recorder.stop();
+        }
+        self.release();
+        self.closing_files.wait();
+    }
+
+    /// Follows changes of the recording configuration. A file being recorded is closed
+    /// when its settings changed.
+    fn update_recorders(&mut self) {
+        let recording = self.shared.recording();
+        self.recording_annotation = recording.annotation;
+
+        let motion_settings = self.motion_recorder.as_ref().map(MotionRecorder::settings);
+        if motion_settings != recording.motion.as_ref() {
+            if let Some(mut motion_recorder) = self.motion_recorder.take() {
+                motion_recorder.stop();
+            }
+            self.motion_recorder = recording.motion.map(|settings| {
+                MotionRecorder::new(&self.name, settings, self.closing_files.clone())
+            });
+        }
+
+        let continuous_settings = self.segment_recorder.as_ref().map(SegmentRecorder::settings);
+        if continuous_settings != recording.continuous.as_ref() {
+            if let Some(mut segment_recorder) = self.segment_recorder.take() {
+                segment_recorder.stop();
+            }
+            self.segment_recorder = recording.continuous.map(|settings| {
+                SegmentRecorder::new(&self.name, settings, self.closing_files.clone())
+            });
+        }
+
+        let timelapse_settings = self
+            .timelapse_recorder
+            .as_ref()
+            .map(TimelapseRecorder::settings);
+        if timelapse_settings != recording.timelapse.as_ref() {
+            if let Some(mut timelapse_recorder) = self.timelapse_recorder.take() {
+                timelapse_recorder.stop();
+            }
+            self.timelapse_recorder = recording.timelapse.map(|settings| {
+                TimelapseRecorder::new(&self.name, settings, self.closing_files.clone())
+            });
+        }
+    }
+
+    fn update_snapshotter(&mut self) {
+        let snapshots = self.shared.snapshots();
+        if *self.snapshotter.settings() != snapshots {
+            self.snapshotter = Snapshotter::new(snapshots);
+        }
+    }
+
+    fn record(&mut self, frame: &ImageFrame, settings: &VideoChannelSettings) {
+        if self.motion_recorder.is_none()
+            && self.segment_recorder.is_none()
+            && self.timelapse_recorder.is_none()
+        {
+            return;
+        }
+        let motion = self.shared.motion();
+        let annotated_frame = match self.annotate(&frame.image, &motion, self.recording_annotation)
+        {
+            Ok(annotated_image) => annotated_image.map(|image| ImageFrame {
+                image,
+                instant: frame.instant,
+            }),
+            Err(e) => {
+                println!(
+                    "Could not annotate the recording of camera {}: {}",
+                    self.name, e
+                );
+                None
+            }
+        };
+        let frame = annotated_frame.as_ref().unwrap_or(frame);
+
+        let fps = self.recording_fps(settings);
+        if let Some(motion_recorder) = &mut self.motion_recorder {
+            motion_recorder.process(frame, self.shared.motion().has_motion(), fps);
+        }
+        if let Some(segment_recorder) = &mut self.segment_recorder {
+            segment_recorder.process(frame, fps);
+        }
+        if let Some(timelapse_recorder) = &mut self.timelapse_recorder {
+            timelapse_recorder.process(frame);
+        }
+    }
+
+    /*
+    The recordings must play in real time, so the rate at which frames are actually read
+    is used once it is measured, capped at the rate reported by the stream. Until then,
+    the reported rate is used, capped at the configured one, as the frames in between are
+    skipped.
+    */
+    fn recording_fps(&self, settings: &VideoChannelSettings) -> f64 {
+        let read_fps = 1.0 / settings.frame_duration.as_secs_f64();
+        let stream_fps = self.stream_fps.unwrap_or(f64::INFINITY);
+        match self.frame_rate.fps() {
+            Some(measured_fps) => measured_fps.min(stream_fps),
+            None => read_fps.min(stream_fps),
+        }
+    }
+
+    fn take_snapshots(&mut self, frame: &ImageFrame, checked_motion: Option<&MotionState>) {
+        let motion = self.shared.motion();
+        let snapshots = self.snapshotter.process(frame, checked_motion, &motion);
+        if snapshots.is_empty() {
+            return;
+        }
+
+        let settings = self.snapshotter.settings().clone();
+        for snapshot in snapshots {
+            let result = self
+                .annotate(&snapshot.frame.image, &snapshot.motion, settings.annotation)
+                .and_then(|annotated_image| {
+                    let image = annotated_image.as_ref().unwrap_or(&snapshot.frame.image);
+                    snapshot::save_snapshot(image, &self.name, snapshot.trigger, &settings.files)
+                });
+            if let Err(e) = result {
+                println!("Could not save snapshot of camera {}: {}", self.name, e);
+            }
+        }
+    }
+
+    /// Copy of the image with the annotations, or None when they are raw. The frames
+    /// are shared with the display, so they are never drawn on.
+    fn annotate(
+        &self,
+        image: &Mat,
+        motion: &MotionState,
+        annotation: FrameAnnotation,
+    ) -> VideoResult<Option<Mat>> {
+        if !annotation.draws_motion_boxes() {
+            return Ok(None);
+        }
+
+        let mut image = image.clone();
+        motion.draw_regions(&mut image)?;
+        if annotation.draws_overlays() {
+            let overlay_texts = self.shared.overlay_texts();
+            let lines: Vec<_> = overlay_texts.iter().map(OverlayText::line).collect();
+            text_layout::draw_text_lines(&mut image, &lines, |_| 0)?;
+        }
+        Ok(Some(image))
+    }
+
+    /// Closes the current clip and segment. The timelapse keeps the file of the day open,
+    /// so a stream that comes back goes on adding to it.
+    fn stop_recorders(&mut self) {
+        if let Some(motion_recorder) = &mut self.motion_recorder {
+            motion_recorder.stop();
+        }
+        if let Some(segment_recorder) = &mut self.segment_recorder {
+            segment_recorder.stop();
+        }
+    }
+
+    fn connect(&mut self, settings: &VideoChannelSettings) {
+        match open_camera(&self.url) {
+            Ok(camera) => {
+                self.stream_fps = stream_fps(&camera);
+                self.frame_rate = FrameRateMeter::default();
+                self.camera = Some(camera);
+                self.backoff.reset();
+
+                let mut status = self.shared.status();
+                status.offline_since = None;
+                status.set_state(&self.name, ChannelState::Connected);
+            }
+            Err(e) => {
+                let delay = self.backoff.schedule_next();
+                println!(
+                    "Could not connect camera {}: {}. Retrying in {:?}",
+                    self.name, e, delay
+                );
+
+                let mut status = self.shared.status();
+                status.last_error = Some(e.to_string());
+                status.offline_since.get_or_insert_with(Instant::now);
+                if self.backoff.attempts() >= settings.max_reconnect_attempts {
+                    status.set_state(&self.name, ChannelState::Failed);
+                } else {
+                    status.set_state(&self.name, ChannelState::Reconnecting);
+                }
+            }
+        }
+    }
+
+    fn read_frame(&mut self) -> Option<Mat> {
+        let camera = self.camera.as_mut()?;
+
+        let mut image = Mat::default();
+        match camera.read(&mut image) {
+            Ok(true) if !image.empty() => Some(image),
+            Ok(_) => {
+                self.connection_lost("empty frame");
+                None
+            }
+            Err(e) => {
+                self.connection_lost(&e.to_string());
+                None
+            }
+        }
+    }
+
+    /// Returns the result of the movement check, None if no check was done.
+    fn detect_motion(
+        &mut self,
+        image: &Mat,
+        settings: &VideoChannelSettings,
+    ) -> Option<MotionState> {
+        match self.motion_detector.process(image, settings) {
+            Ok(Some(motion)) => {
+                if motion.has_motion() && !self.shared.motion().has_motion() {
+                    println!("Motion detected on camera {}", self.name);
+                }
+                self.shared.set_motion(motion.clone());
+                Some(motion)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                println!("Motion detection failed for camera {}: {}", self.name, e);
+                self.shared.status().last_error = Some(e.to_string());
+                None
+            }
+        }
+    }
+
+    fn connection_lost(&mut self, reason: &str) {
+        println!("Camera {} lost its stream: {}", self.name, reason);
+        self.release();
+        self.shared.set_latest_frame(None);
+        self.shared.set_motion(MotionState::default());
+        self.motion_detector.reset();
+        self.stop_recorders();
+        self.snapshotter.reset();
+        self.backoff.reset();
+
+        let mut status = self.shared.status();
+        status.last_error = Some(reason.to_string());
+        status.offline_since = Some(Instant::now());
+        status.set_state(&self.name, ChannelState::Reconnecting);
+    }
+
+    fn release(&mut self) {
+        if let Some(mut camera) = self.camera.take() {
+            if let Err(e) = camera.release() {
+                println!("Could not release camera {}: {}", self.name, e);
+            }
+        }
+    }
+}
+
+/// Rate at which the frames of a stream are read, averaged so that one late frame
+/// doesn't change it much.
+#[derive(Default)]
+struct FrameRateMeter {
+    last_frame: Option<Instant>,
+    frame_interval_secs: Option<f64>,
+}
+
+impl FrameRateMeter {
+    fn add_frame(&mut self, instant: Instant) {
+        if let Some(last_frame) = self.last_frame {
+            let interval_secs = instant.duration_since(last_frame).as_secs_f64();
+            self.frame_interval_secs = Some(match self.frame_interval_secs {
+                Some(average) => average + (interval_secs - average) * FRAME_RATE_SMOOTHING,
+                None => interval_secs,
+            });
+        }
+        self.last_frame = Some(instant);
+    }
+
+    /// None until two frames were read.
+    fn fps(&self) -> Option<f64> {
+        self.frame_interval_secs
+            .filter(|interval_secs| *interval_secs > 0.0)
+            .map(|interval_secs| 1.0 / interval_secs)
+    }
+}
+
+/// Frame rate reported by the stream, None when it reports none or an implausible one.
+fn stream_fps(camera: &VideoCapture) -> Option<f64> {
+    camera
+        .get(CAP_PROP_FPS)
+        .ok()
+        .filter(|fps| (1.0..=MAX_STREAM_FPS).contains(fps))
+}
+
+fn open_camera(url: &str) -> VideoResult<VideoCapture> {
+    let mut camera = VideoCapture::from_file(url, CAP_ANY)?;
+    if !camera.is_opened()? {
+        return Err(VideoStreamError::OpenStreamError(url.to_string()));
+    }
+
+    /*
+    Ensure the buffer is small enough that we are always reading the latest
+    image from the stream.
+    */
+    let _ = camera.set(CAP_PROP_BUFFERSIZE, 1.0);
+
+    Ok(camera)
+}
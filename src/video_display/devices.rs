@@ -0,0 +1,53 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const V4L2_SYSFS_PATH: &str = "/sys/class/video4linux";
+
+pub struct CaptureDevice {
+    pub path: PathBuf,
+    pub name: String,
+    /// Index of the node within its physical device. Webcams usually expose the
+    /// capture stream at index 0 and metadata streams at higher indexes.
+    pub index: u32,
+}
+
+pub(crate) fn list_capture_devices() -> io::Result<Vec<CaptureDevice>> {
+    let sysfs_path = Path::new(V4L2_SYSFS_PATH);
+    if !sysfs_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut devices = Vec::new();
+    for entry in fs::read_dir(sysfs_path)? {
+        let entry = entry?;
+        let node_name = entry.file_name().to_string_lossy().to_string();
+        if !node_name.starts_with("video") {
+            continue;
+        }
+
+        devices.push(CaptureDevice {
+            path: Path::new("/dev").join(&node_name),
+            name: read_attribute(&entry.path(), "name").unwrap_or_default(),
+            index: read_attribute(&entry.path(), "index")
+                .and_then(|index| index.parse().ok())
+                .unwrap_or(0),
+        });
+    }
+
+    devices.sort_by_key(|device| device_number(&device.path));
+    Ok(devices)
+}
+
+fn read_attribute(device_path: &Path, attribute: &str) -> Option<String> {
+    fs::read_to_string(device_path.join(attribute))
+        .ok()
+        .map(|value| value.trim().to_string())
+}
+
+fn device_number(path: &Path) -> u32 {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.trim_start_matches("video").parse().ok())
+        .unwrap_or(u32::MAX)
+}
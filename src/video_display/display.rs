@@ -1,3 +1,4 @@
+use crate::video_display::key_bindings::{DisplayCommand, KeyBindings};
 pub(crate) use crate::video_display::VideoResult;
 pub(crate) use crate::video_display::VideoStreamError;
 use opencv::core::Mat;
@@ -37,11 +38,24 @@ impl DisplayWindow {
         Ok(())
     }
 
-    pub fn stop_key_pressed(&self, duration: Duration) -> VideoResult<bool> {
+    /// Waits up to `duration` for a key press and returns the command bound to the key.
+    pub fn wait_command(
+        &self,
+        duration: Duration,
+        key_bindings: &KeyBindings,
+    ) -> VideoResult<Option<DisplayCommand>> {
         let frame_duration = max(duration.as_millis() as i32, 1);
-        if highgui::wait_key(frame_duration)? == 'q' as i32 {
-            return Ok(true);
+        let key_code = highgui::wait_key_ex(frame_duration)?;
+        if key_code < 0 {
+            return Ok(None);
         }
-        Ok(false)
+        // GTK reports the active modifiers, like Num Lock, above the lower 16 bits
+        Ok(key_bindings.command_for(key_code & 0xFFFF))
+    }
+}
+
+impl Drop for DisplayWindow {
+    fn drop(&mut self) {
+        let _ = highgui::destroy_window(self.window_name.as_str());
     }
 }
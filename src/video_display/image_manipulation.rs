@@ -1,68 +1,73 @@
+use crate::config::OverlayStyle;
+use crate::video_display::text_renderer;
+use crate::video_display::text_renderer::TextRenderer;
 use crate::video_display::VideoResult;
-use opencv::core::{Mat, MatTraitConst, Point, Size};
+use opencv::core::{Mat, Point, Scalar, Size, CV_8UC3};
 use opencv::imgproc;
-use opencv::imgproc::{get_text_size, put_text};
+use serde::Deserialize;
+use std::path::PathBuf;
 
-pub(crate) enum TextPosition {
+const SLATE_FONT_SCALE: f64 = 1.0;
+const SLATE_FONT_THICKNESS: u32 = 2;
+
+/// Corner, or centre, of the image at which something is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextPosition {
     TopLeft,
     TopRight,
     BottomLeft,
     BottomRight,
+    Center,
 }
 
-pub(crate) fn write_text(image: &mut Mat, line_index: u8, text: &str, position: TextPosition) {
-    let color = opencv::core::Scalar::new(0.0, 255.0, 0.0, 0.0);
-    let text_font = imgproc::FONT_HERSHEY_SIMPLEX;
-    let font_scale = 1.0;
-    let font_thickness = 2;
-    let mut baseline = 0;
-    let padding = 10;
+/// Converts an [red, green, blue] colour from the configuration to OpenCV's BGR order.
+pub(crate) fn to_scalar([red, green, blue]: [u8; 3]) -> Scalar {
+    Scalar::new(blue as f64, green as f64, red as f64, 0.0)
+}
 
-    // TODO: Error handling
-    let image_size = image.size().unwrap();
-    let text_size =
-        get_text_size(text, text_font, font_scale, font_thickness, &mut baseline).unwrap();
+/// Renderer of the "no signal" slate, with the font file when there is one.
+pub(crate) fn slate_renderer(font_file: Option<PathBuf>) -> Box<dyn TextRenderer> {
+    text_renderer::create_renderer(&OverlayStyle {
+        font_file,
+        scale: SLATE_FONT_SCALE,
+        thickness: SLATE_FONT_THICKNESS,
+        ..OverlayStyle::default()
+    })
+}
 
-    let line_height: i32 = text_size.height + baseline + padding;
-    let first_line_offset: i32 = 60;
-    let line_position: i32 = line_index as i32 * line_height + first_line_offset;
+/// Creates a dark frame of the given size with the lines of text centered on it.
+pub(crate) fn create_slate(
+    size: Size,
+    lines: &[String],
+    renderer: &dyn TextRenderer,
+) -> VideoResult<Mat> {
+    let background = Scalar::new(40.0, 40.0, 40.0, 0.0);
+    let color = Scalar::new(255.0, 255.0, 255.0, 0.0);
+    let line_spacing = 20;
 
-    let origin = calculate_text_origin(&text_size, &image_size, padding, line_position, &position);
+    let mut image = Mat::new_rows_cols_with_default(size.height, size.width, CV_8UC3, background)?;
 
-    let _ = put_text(
-        image,
-        text,
-        origin,
-        text_font,
-        font_scale,
-        color,
-        font_thickness,
-        imgproc::LINE_8,
-        false,
-    );
-}
+    let mut text_sizes = Vec::with_capacity(lines.len());
+    for line in lines {
+        let (text_size, _) = renderer.measure(line)?;
+        text_sizes.push(text_size);
+    }
+
+    let total_height: i32 = text_sizes
+        .iter()
+        .map(|text_size| text_size.height + line_spacing)
+        .sum();
+    let mut line_position = (size.height - total_height) / 2;
 
-fn calculate_text_origin(
-    text_size: &Size,
-    image_size: &Size,
-    padding: i32,
-    line_position: i32,
-    position: &TextPosition,
-) -> Point {
-    let y_padding = padding + line_position;
-    match position {
-        TextPosition::TopLeft => Point::new(padding, y_padding),
-        TextPosition::BottomLeft => {
-            Point::new(padding, image_size.height - (text_size.height + y_padding))
-        }
-        TextPosition::TopRight => {
-            Point::new(image_size.width - (text_size.width + padding), y_padding)
-        }
-        TextPosition::BottomRight => Point::new(
-            image_size.width - (text_size.width + y_padding),
-            image_size.height - (text_size.height + y_padding),
-        ),
+    for (line, text_size) in lines.iter().zip(text_sizes) {
+        line_position += text_size.height;
+        let origin = Point::new((size.width - text_size.width).max(0) / 2, line_position);
+        renderer.draw(&mut image, line, origin, color)?;
+        line_position += line_spacing;
     }
+
+    Ok(image)
 }
 
 pub(crate) fn to_gray_image(image: &Mat) -> VideoResult<Mat> {
@@ -0,0 +1,94 @@
+use crate::config::KeysConfig;
+use std::collections::HashMap;
+
+/*
+Key codes returned by `highgui::wait_key_ex` with the GTK backend. Printable keys
+are reported as their ASCII code, special keys as their X11 keysym.
+*/
+const KEY_TAB: i32 = 9;
+const KEY_ENTER: i32 = 13;
+const KEY_ESCAPE: i32 = 27;
+const KEY_SPACE: i32 = 32;
+const KEY_LEFT: i32 = 65361;
+const KEY_UP: i32 = 65362;
+const KEY_RIGHT: i32 = 65363;
+const KEY_DOWN: i32 = 65364;
+
+/// Action requested from the keyboard while the display is running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayCommand {
+    Quit,
+    /// Shows the channel at the given position and pauses automatic switching.
+    SelectChannel(usize),
+    NextChannel,
+    PreviousChannel,
+    /// Pauses or resumes automatic switching.
+    TogglePause,
+    /// Keeps showing the current frame, or goes back to the live stream.
+    ToggleFreeze,
+    /// Saves the displayed image to a file.
+    Snapshot,
+}
+
+pub struct KeyBindings {
+    commands: HashMap<i32, DisplayCommand>,
+}
+
+impl KeyBindings {
+    /// Builds the bindings from a validated configuration. Key names that can't be
+    /// parsed are ignored.
+    pub fn from_config(config: &KeysConfig) -> Self {
+        let actions = [
+            (DisplayCommand::Quit, &config.quit),
+            (DisplayCommand::NextChannel, &config.next_channel),
+            (DisplayCommand::PreviousChannel, &config.previous_channel),
+            (DisplayCommand::TogglePause, &config.toggle_pause),
+            (DisplayCommand::ToggleFreeze, &config.toggle_freeze),
+            (DisplayCommand::Snapshot, &config.snapshot),
+        ];
+
+        let mut commands = HashMap::new();
+        for (command, keys) in actions {
+            for key in keys {
+                if let Some(code) = parse_key(key) {
+                    commands.insert(code, command);
+                }
+            }
+        }
+        for (index, key) in config.channels.iter().enumerate() {
+            if let Some(code) = parse_key(key) {
+                commands.insert(code, DisplayCommand::SelectChannel(index));
+            }
+        }
+
+        Self { commands }
+    }
+
+    pub fn command_for(&self, key_code: i32) -> Option<DisplayCommand> {
+        self.commands.get(&key_code).copied()
+    }
+}
+
+/// Converts a key name from the configuration to the code returned by the display.
+/// Accepts a single character, a named key ("space", "escape", "enter", "tab", "left",
+/// "right", "up", "down") or a raw key code of two or more digits.
+pub fn parse_key(name: &str) -> Option<i32> {
+    match name.to_ascii_lowercase().as_str() {
+        "tab" => Some(KEY_TAB),
+        "enter" => Some(KEY_ENTER),
+        "escape" | "esc" => Some(KEY_ESCAPE),
+        "space" => Some(KEY_SPACE),
+        "left" => Some(KEY_LEFT),
+        "up" => Some(KEY_UP),
+        "right" => Some(KEY_RIGHT),
+        "down" => Some(KEY_DOWN),
+        _ => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(key), None) if key.is_ascii() => Some(key as i32),
+                (Some(_), Some(_)) => name.parse().ok().filter(|code| *code > 0),
+                _ => None,
+            }
+        }
+    }
+}
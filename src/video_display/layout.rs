@@ -0,0 +1,373 @@
+use crate::config::{LayoutMode, OverlayStyle, PictureInPictureConfig};
+use crate::video_display::image_manipulation;
+use crate::video_display::image_manipulation::TextPosition;
+use crate::video_display::text_renderer;
+use crate::video_display::text_renderer::TextRenderer;
+use crate::video_display::VideoResult;
+use opencv::core::{Mat, MatTraitConst, Point, Rect, Scalar, Size, CV_8UC3};
+use opencv::imgproc;
+use std::path::PathBuf;
+
+/// Largest number of small tiles of the large and small layout.
+const MAX_SMALL_TILES: usize = 7;
+const LABEL_PADDING: i32 = 6;
+const LABEL_FONT_SCALE: f64 = 0.6;
+const LABEL_FONT_THICKNESS: u32 = 1;
+
+/// Area of the composed image in which a channel is drawn.
+pub(crate) struct Tile {
+    pub(crate) channel_index: usize,
+    pub(crate) area: Rect,
+}
+
+/// Splits the display into the tiles of the layout. The current channel is always
+/// one of the tiles.
+pub(crate) fn arrange(
+    layout: LayoutMode,
+    display_size: Size,
+    channel_count: usize,
+    current_channel: usize,
+) -> Vec<Tile> {
+    match layout {
+        LayoutMode::Single => vec![Tile {
+            channel_index: current_channel,
+            area: Rect::new(0, 0, display_size.width, display_size.height),
+        }],
+        LayoutMode::Grid2x2 => arrange_grid(2, display_size, channel_count, current_channel),
+        LayoutMode::Grid3x3 => arrange_grid(3, display_size, channel_count, current_channel),
+        LayoutMode::LargeAndSmall => {
+            arrange_large_and_small(display_size, channel_count, current_channel)
+        }
+    }
+}
+
+fn arrange_grid(
+    columns: usize,
+    display_size: Size,
+    channel_count: usize,
+    current_channel: usize,
+) -> Vec<Tile> {
+    let tiles_per_page = columns * columns;
+    let first_channel = (current_channel / tiles_per_page) * tiles_per_page;
+    let last_channel = usize::min(first_channel + tiles_per_page, channel_count);
+
+    (first_channel..last_channel)
+        .enumerate()
+        .map(|(position, channel_index)| Tile {
+            channel_index,
+            area: grid_cell(
+                display_size,
+                columns,
+                position % columns,
+                position / columns,
+                1,
+            ),
+        })
+        .collect()
+}
+
+/*
+The display is split in a grid in which the current channel takes every cell but
+the last column and the last row. The other channels fill the last column from the
+top and then the last row from the left. The grid has at least 3 columns, so the
+large tile always covers 2x2 cells.
+*/
+fn arrange_large_and_small(
+    display_size: Size,
+    channel_count: usize,
+    current_channel: usize,
+) -> Vec<Tile> {
+    if channel_count <= 1 {
+        return arrange(
+            LayoutMode::Single,
+            display_size,
+            channel_count,
+            current_channel,
+        );
+    }
+
+    let small_tile_count = usize::min(channel_count - 1, MAX_SMALL_TILES);
+    let columns = usize::max(3, (small_tile_count + 1).div_ceil(2));
+
+    let mut tiles = vec![Tile {
+        channel_index: current_channel,
+        area: grid_cell(display_size, columns, 0, 0, columns - 1),
+    }];
+
+    let other_channels = (1..channel_count)
+        .map(|offset| (current_channel + offset) % channel_count)
+        .take(small_tile_count);
+    for (position, channel_index) in other_channels.enumerate() {
+        let (column, row) = if position < columns {
+            (columns - 1, position)
+        } else {
+            (position - columns, columns - 1)
+        };
+        tiles.push(Tile {
+            channel_index,
+            area: grid_cell(display_size, columns, column, row, 1),
+        });
+    }
+
+    tiles
+}
+
+/// Area of `span` x `span` cells of a square grid, starting at the given cell.
+fn grid_cell(display_size: Size, columns: usize, column: usize, row: usize, span: usize) -> Rect {
+    let columns = columns as i32;
+    let (column, row, span) = (column as i32, row as i32, span as i32);
+
+    let x = column * display_size.width / columns;
+    let y = row * display_size.height / columns;
+    let right = (column + span) * display_size.width / columns;
+    let bottom = (row + span) * display_size.height / columns;
+
+    Rect::new(x, y, right - x, bottom - y)
+}
+
+/// Renderer of the channel labels, with the font file when there is one.
+pub(crate) fn label_renderer(font_file: Option<PathBuf>) -> Box<dyn TextRenderer> {
+    text_renderer::create_renderer(&OverlayStyle {
+        font_file,
+        scale: LABEL_FONT_SCALE,
+        thickness: LABEL_FONT_THICKNESS,
+        ..OverlayStyle::default()
+    })
+}
+
+pub(crate) fn create_canvas(size: Size) -> VideoResult<Mat> {
+    Ok(Mat::new_rows_cols_with_default(
+        size.height,
+        size.width,
+        CV_8UC3,
+        Scalar::all(0.0),
+    )?)
+}
+
+/// Draws the image scaled to fit the tile, keeping its aspect ratio, with the
+/// channel name in the bottom left corner. The highlighted tile gets a border.
+pub(crate) fn draw_tile(
+    canvas: &mut Mat,
+    image: &Mat,
+    area: Rect,
+    label: &str,
+    label_renderer: &dyn TextRenderer,
+    highlighted: bool,
+) -> VideoResult<()> {
+    draw_scaled(canvas, image, area)?;
+    draw_label(canvas, area, label, label_renderer)?;
+
+    let (border_color, border_thickness) = if highlighted {
+        (Scalar::new(0.0, 255.0, 255.0, 0.0), 3)
+    } else {
+        (Scalar::new(80.0, 80.0, 80.0, 0.0), 1)
+    };
+    imgproc::rectangle(
+        canvas,
+        area,
+        border_color,
+        border_thickness,
+        imgproc::LINE_8,
+        0,
+    )?;
+
+    Ok(())
+}
+
+/// Draws the image over the given corner of the canvas, with the size, margin and border
+/// of the inset configuration. Returns the area covered by the inset and its border.
+pub(crate) fn draw_inset(
+    canvas: &mut Mat,
+    image: &Mat,
+    inset: &PictureInPictureConfig,
+) -> VideoResult<Rect> {
+    let canvas_size = canvas.size()?;
+    let image_size = image.size()?;
+    if image_size.width == 0 || image_size.height == 0 {
+        return Ok(Rect::default());
+    }
+
+    let border_width = inset.border_width as i32;
+    let margin = inset.margin as i32 + border_width;
+    let width = (canvas_size.width * inset.size_percent as i32 / 100).max(1);
+    let height = (width * image_size.height / image_size.width)
+        .clamp(1, (canvas_size.height - 2 * margin).max(1));
+
+    let x = match inset.corner {
+        TextPosition::TopLeft | TextPosition::BottomLeft => margin,
+        TextPosition::TopRight | TextPosition::BottomRight => canvas_size.width - width - margin,
+        TextPosition::Center => (canvas_size.width - width) / 2,
+    };
+    let y = match inset.corner {
+        TextPosition::TopLeft | TextPosition::TopRight => margin,
+        TextPosition::BottomLeft | TextPosition::BottomRight => {
+            canvas_size.height - height - margin
+        }
+        TextPosition::Center => (canvas_size.height - height) / 2,
+    };
+    let area = Rect::new(x, y, width, height);
+    let bordered_area = Rect::new(
+        x - border_width,
+        y - border_width,
+        width + 2 * border_width,
+        height + 2 * border_width,
+    );
+    if bordered_area.x < 0
+        || bordered_area.y < 0
+        || bordered_area.x + bordered_area.width > canvas_size.width
+        || bordered_area.y + bordered_area.height > canvas_size.height
+    {
+        // The margins don't leave room for the inset on this display
+        return Ok(Rect::default());
+    }
+
+    if border_width > 0 {
+        imgproc::rectangle(
+            canvas,
+            bordered_area,
+            image_manipulation::to_scalar(inset.border_color),
+            imgproc::FILLED,
+            imgproc::LINE_8,
+            0,
+        )?;
+    }
+    draw_scaled(canvas, image, area)?;
+
+    Ok(bordered_area)
+}
+
+/// Draws the image centered in the area, scaled to fit it with its aspect ratio kept.
+fn draw_scaled(canvas: &mut Mat, image: &Mat, area: Rect) -> VideoResult<()> {
+    let image_size = image.size()?;
+    if image_size.width == 0 || image_size.height == 0 {
+        return Ok(());
+    }
+
+    let scale = f64::min(
+        area.width as f64 / image_size.width as f64,
+        area.height as f64 / image_size.height as f64,
+    );
+    let scaled_size = Size::new(
+        ((image_size.width as f64 * scale) as i32).clamp(1, area.width),
+        ((image_size.height as f64 * scale) as i32).clamp(1, area.height),
+    );
+
+    let mut scaled_image = Mat::default();
+    imgproc::resize(
+        image,
+        &mut scaled_image,
+        scaled_size,
+        0.0,
+        0.0,
+        imgproc::INTER_AREA,
+    )?;
+
+    let position = Rect::new(
+        area.x + (area.width - scaled_size.width) / 2,
+        area.y + (area.height - scaled_size.height) / 2,
+        scaled_size.width,
+        scaled_size.height,
+    );
+    let mut target = Mat::roi_mut(canvas, position)?;
+    scaled_image.copy_to(&mut target)?;
+
+    Ok(())
+}
+
+fn draw_label(
+    canvas: &mut Mat,
+    area: Rect,
+    label: &str,
+    renderer: &dyn TextRenderer,
+) -> VideoResult<()> {
+    let (text_size, baseline) = renderer.measure(label)?;
+
+    let background = Rect::new(
+        area.x,
+        area.y + area.height - (text_size.height + baseline + 2 * LABEL_PADDING),
+        text_size.width + 2 * LABEL_PADDING,
+        text_size.height + baseline + 2 * LABEL_PADDING,
+    );
+    imgproc::rectangle(
+        canvas,
+        background,
+        Scalar::all(0.0),
+        imgproc::FILLED,
+        imgproc::LINE_8,
+        0,
+    )?;
+
+    renderer.draw(
+        canvas,
+        label,
+        Point::new(
+            area.x + LABEL_PADDING,
+            area.y + area.height - (baseline + LABEL_PADDING),
+        ),
+        Scalar::new(255.0, 255.0, 255.0, 0.0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DISPLAY_SIZE: Size = Size {
+        width: 1200,
+        height: 900,
+    };
+
+    fn overlaps(a: Rect, b: Rect) -> bool {
+        a.x < b.x + b.width && b.x < a.x + a.width && a.y < b.y + b.height && b.y < a.y + a.height
+    }
+
+    /// Sizes of the tiles of the large and small layout, the large one first.
+    fn large_and_small_tile_sizes(channel_count: usize) -> Vec<(i32, i32)> {
+        let tiles = arrange(LayoutMode::LargeAndSmall, DISPLAY_SIZE, channel_count, 1);
+
+        assert_eq!(tiles[0].channel_index, 1);
+        for (index, tile) in tiles.iter().enumerate() {
+            for other in &tiles[index + 1..] {
+                assert_ne!(tile.channel_index, other.channel_index);
+                assert!(
+                    !overlaps(tile.area, other.area),
+                    "{:?} overlaps {:?}",
+                    tile.area,
+                    other.area
+                );
+            }
+        }
+        tiles
+            .iter()
+            .map(|tile| (tile.area.width, tile.area.height))
+            .collect()
+    }
+
+    #[test]
+    fn large_and_small_of_2_channels() {
+        assert_eq!(large_and_small_tile_sizes(2), vec![(800, 600), (400, 300)]);
+    }
+
+    #[test]
+    fn large_and_small_of_4_channels() {
+        assert_eq!(
+            large_and_small_tile_sizes(4),
+            vec![(800, 600), (400, 300), (400, 300), (400, 300)]
+        );
+    }
+
+    #[test]
+    fn large_and_small_of_5_channels() {
+        assert_eq!(
+            large_and_small_tile_sizes(5),
+            vec![(800, 600), (400, 300), (400, 300), (400, 300), (400, 300)]
+        );
+    }
+
+    #[test]
+    fn large_and_small_of_8_channels() {
+        let mut expected = vec![(900, 675)];
+        expected.extend([(300, 225); 7]);
+        assert_eq!(large_and_small_tile_sizes(8), expected);
+    }
+}
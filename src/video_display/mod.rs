@@ -2,19 +2,37 @@ use opencv::core::Mat;
 use std::fmt;
 use std::time::Instant;
 
+pub(crate) mod capture_worker;
+pub(crate) mod devices;
 pub(crate) mod display;
 pub(crate) mod image_manipulation;
+pub(crate) mod key_bindings;
+pub(crate) mod layout;
+pub(crate) mod motion_detection;
+pub(crate) mod probe;
+pub(crate) mod recording;
+pub(crate) mod retention;
+pub(crate) mod snapshot;
+pub(crate) mod text_layout;
+pub(crate) mod text_renderer;
+pub(crate) mod reconnection;
 pub(crate) mod video_channel;
 
-struct ImageFrame {
-    image: Mat,
-    instant: Instant,
+#[derive(Clone)]
+pub(crate) struct ImageFrame {
+    pub(crate) image: Mat,
+    pub(crate) instant: Instant,
 }
 
 #[derive(Debug)]
 pub enum VideoStreamError {
     OpenCv(opencv::Error),
     CreateWindowError(opencv::Error),
+    OpenStreamError(String),
+    StreamUnavailable(String),
+    WriteImageError(String),
+    FontError(String, String),
+    VideoWriterError(String, String),
 }
 
 impl fmt::Display for VideoStreamError {
@@ -22,6 +40,19 @@ impl fmt::Display for VideoStreamError {
         match self {
             VideoStreamError::OpenCv(e) => write!(f, "OpenCV error: {}", e),
             VideoStreamError::CreateWindowError(e) => write!(f, "Create Window error: {}", e),
+            VideoStreamError::OpenStreamError(url) => write!(f, "Could not open stream {}", url),
+            VideoStreamError::StreamUnavailable(name) => {
+                write!(f, "Stream of camera {} is unavailable", name)
+            }
+            VideoStreamError::WriteImageError(path) => {
+                write!(f, "Could not write image {}", path)
+            }
+            VideoStreamError::FontError(path, message) => {
+                write!(f, "Could not load font {}: {}", path, message)
+            }
+            VideoStreamError::VideoWriterError(path, message) => {
+                write!(f, "Could not write video {}: {}", path, message)
+            }
         }
     }
 }
@@ -0,0 +1,187 @@
+use crate::video_display::video_channel::VideoChannelSettings;
+use crate::video_display::{image_manipulation, ImageFrame, VideoResult};
+use opencv::core::{Mat, Point, Rect, Vector};
+use opencv::imgproc;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+// TODO: does it make sense for these to be configurable?
+const IMG_DIFF_THRESHOLD: f64 = 10.0;
+const MIN_CONTOUR_AREA: f64 = 10000.0;
+const REGION_COLOR: [u8; 3] = [255, 0, 0];
+
+/// Result of the latest movement check of a channel.
+#[derive(Debug, Clone, Default)]
+pub struct MotionState {
+    /// Number of moving regions larger than the minimum contour area.
+    pub moving_parts: u32,
+    /// Sum of the contour areas of the moving regions, in pixels.
+    pub moving_area: f64,
+    /// Bounding boxes of the moving regions.
+    pub regions: Vec<Rect>,
+    pub last_motion: Option<Instant>,
+}
+
+impl MotionState {
+    pub fn has_motion(&self) -> bool {
+        self.moving_parts > 0
+    }
+
+    /// Draws a box around every moving region.
+    pub(crate) fn draw_regions(&self, image: &mut Mat) -> VideoResult<()> {
+        for region in &self.regions {
+            imgproc::rectangle(
+                image,
+                *region,
+                image_manipulation::to_scalar(REGION_COLOR),
+                3,
+                imgproc::LineTypes::LINE_8 as i32,
+                0,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares the frames of a channel against an older background frame to find
+/// the regions where something moved.
+pub(crate) struct MotionDetector {
+    frame_buffer: VecDeque<ImageFrame>,
+    last_mvn_check: Instant,
+    last_motion: Option<Instant>,
+}
+
+impl MotionDetector {
+    pub fn new() -> Self {
+        Self {
+            frame_buffer: VecDeque::new(),
+            last_mvn_check: Instant::now(),
+            last_motion: None,
+        }
+    }
+
+    /// Forgets the buffered frames, e.g. after the stream was reopened.
+    pub fn reset(&mut self) {
+        self.frame_buffer.clear();
+    }
+
+    /// Checks the image for movement when the check interval has passed. Returns the
+    /// new motion state, or None if no check was done.
+    pub fn process(
+        &mut self,
+        image: &Mat,
+        settings: &VideoChannelSettings,
+    ) -> VideoResult<Option<MotionState>> {
+        if self.last_mvn_check.elapsed() < settings.mvn_update_interval {
+            return Ok(None);
+        }
+
+        self.last_mvn_check = Instant::now();
+        let background = self.get_background_image(settings);
+
+        self.frame_buffer.push_back(ImageFrame {
+            image: image_manipulation::to_gray_image(image)?,
+            instant: Instant::now(),
+        });
+
+        let current_frame = self.frame_buffer.back().unwrap();
+        let Some(background_image) = background else {
+            println!("No background image found for {:?}", current_frame.instant);
+            return Ok(None);
+        };
+
+        let img_diff = get_image_diff(&current_frame.image, &background_image.image)?;
+        let contours = get_movement_contours(&img_diff)?;
+
+        let mut motion = MotionState::default();
+        for contour in &contours {
+            let area = imgproc::contour_area(&contour, false)?;
+
+            if area < MIN_CONTOUR_AREA {
+                continue;
+            }
+            motion.moving_parts += 1;
+            motion.moving_area += area;
+            motion.regions.push(imgproc::bounding_rect(&contour)?);
+        }
+
+        if motion.has_motion() {
+            self.last_motion = Some(Instant::now());
+        }
+        motion.last_motion = self.last_motion;
+
+        Ok(Some(motion))
+    }
+
+    fn get_background_image(&mut self, settings: &VideoChannelSettings) -> Option<ImageFrame> {
+        let background_instant = Instant::now() - settings.mvn_comparison_interval;
+
+        while !self.frame_buffer.is_empty()
+            && self.frame_buffer.front().unwrap().instant < background_instant
+        {
+            let background_frame = self.frame_buffer.pop_front().unwrap();
+            let next_frame_instant = background_instant + settings.frame_duration;
+
+            if next_frame_instant > background_instant {
+                return Some(background_frame);
+            }
+        }
+
+        println!(
+            "No background image found. Frame buffer size={}",
+            self.frame_buffer.len()
+        );
+        None
+    }
+}
+
+fn get_image_diff(image: &Mat, background_image: &Mat) -> VideoResult<Mat> {
+    // compare image with background
+    let mut diff = Mat::default();
+    opencv::core::absdiff(&image, &background_image, &mut diff)?;
+
+    // filter the diff img to get only differences larger than the defined threshold
+    let mut thresh_diff = Mat::default();
+    imgproc::threshold(
+        &diff,
+        &mut thresh_diff,
+        IMG_DIFF_THRESHOLD,
+        255.0,
+        imgproc::THRESH_BINARY,
+    )?;
+
+    Ok(thresh_diff)
+}
+
+fn get_movement_contours(img_diff: &Mat) -> VideoResult<Vector<Vector<Point>>> {
+    let kernel = imgproc::get_structuring_element(
+        imgproc::MORPH_RECT,
+        opencv::core::Size {
+            width: 3,
+            height: 3,
+        },
+        Point { x: -1, y: -1 },
+    )?;
+
+    let mut dilated = Mat::default();
+
+    imgproc::dilate(
+        &img_diff,
+        &mut dilated,
+        &kernel,
+        Point { x: -1, y: -1 },
+        2,
+        opencv::core::BorderTypes::BORDER_CONSTANT as i32,
+        opencv::core::Scalar::default(),
+    )?;
+
+    let mut contours = Vector::<Vector<Point>>::new();
+    imgproc::find_contours_def(
+        &dilated,
+        &mut contours,
+        imgproc::RETR_EXTERNAL,
+        imgproc::CHAIN_APPROX_SIMPLE,
+    )?;
+
+    Ok(contours)
+}
@@ -0,0 +1,56 @@
+use crate::video_display::{VideoResult, VideoStreamError};
+use opencv::hub_prelude::{VideoCaptureTrait, VideoCaptureTraitConst};
+use opencv::videoio::{
+    VideoCapture, CAP_ANY, CAP_PROP_FOURCC, CAP_PROP_FPS, CAP_PROP_FRAME_HEIGHT,
+    CAP_PROP_FRAME_WIDTH,
+};
+use std::fmt;
+
+pub struct StreamProperties {
+    pub width: u32,
+    pub height: u32,
+    pub fps: f64,
+    pub codec: String,
+    pub backend: String,
+}
+
+impl fmt::Display for StreamProperties {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Resolution: {}x{}", self.width, self.height)?;
+        writeln!(f, "FPS: {:.2}", self.fps)?;
+        writeln!(f, "Codec: {}", self.codec)?;
+        write!(f, "Backend: {}", self.backend)
+    }
+}
+
+pub(crate) fn probe_stream(url: &str) -> VideoResult<StreamProperties> {
+    let mut camera = VideoCapture::from_file(url, CAP_ANY)?;
+    if !camera.is_opened()? {
+        return Err(VideoStreamError::OpenStreamError(url.to_string()));
+    }
+
+    let properties = StreamProperties {
+        width: camera.get(CAP_PROP_FRAME_WIDTH)? as u32,
+        height: camera.get(CAP_PROP_FRAME_HEIGHT)? as u32,
+        fps: camera.get(CAP_PROP_FPS)?,
+        codec: fourcc_to_string(camera.get(CAP_PROP_FOURCC)? as u32),
+        backend: camera.get_backend_name()?,
+    };
+
+    camera.release()?;
+    Ok(properties)
+}
+
+fn fourcc_to_string(fourcc: u32) -> String {
+    if fourcc == 0 {
+        return "unknown".to_string();
+    }
+
+    fourcc
+        .to_le_bytes()
+        .iter()
+        .map(|&byte| byte as char)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
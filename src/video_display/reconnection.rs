@@ -0,0 +1,59 @@
+use std::time::{Duration, Instant};
+
+/// Exponential backoff between attempts to reopen a video stream.
+pub(crate) struct Backoff {
+    initial_delay: Duration,
+    max_delay: Duration,
+    attempts: u32,
+    next_attempt: Instant,
+}
+
+impl Backoff {
+    pub fn new(initial_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            initial_delay,
+            max_delay,
+            attempts: 0,
+            next_attempt: Instant::now(),
+        }
+    }
+
+    /// Number of failed attempts since the last reset.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn is_due(&self) -> bool {
+        Instant::now() >= self.next_attempt
+    }
+
+    pub fn time_until_due(&self) -> Duration {
+        self.next_attempt.saturating_duration_since(Instant::now())
+    }
+
+    /// Changes the delays used for the next attempts, e.g. after a configuration reload.
+    pub fn set_delays(&mut self, initial_delay: Duration, max_delay: Duration) {
+        self.initial_delay = initial_delay;
+        self.max_delay = max_delay;
+    }
+
+    /// Allows the next attempt right away, starting again from the initial delay.
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+        self.next_attempt = Instant::now();
+    }
+
+    /// Records a failed attempt and returns how long to wait before the next one.
+    pub fn schedule_next(&mut self) -> Duration {
+        let factor = 2u32.saturating_pow(self.attempts);
+        let delay = self
+            .initial_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        self.attempts = self.attempts.saturating_add(1);
+        self.next_attempt = Instant::now() + delay;
+        delay
+    }
+}
@@ -0,0 +1,699 @@
+use crate::config::{FrameAnnotation, OverlayConfig, RecordingConfig};
+use crate::overlay_text_providers;
+use crate::overlay_text_providers::Overlay;
+use crate::video_display::retention;
+use crate::video_display::snapshot;
+use crate::video_display::text_layout;
+use crate::video_display::text_layout::TextLine;
+use crate::video_display::{ImageFrame, VideoResult, VideoStreamError};
+use chrono::{Local, NaiveDate};
+use opencv::core::{Mat, MatTraitConst, Size};
+use opencv::imgproc;
+use opencv::videoio::{VideoWriter, VideoWriterTrait, VideoWriterTraitConst};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::mpsc::{SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Frames queued for the writer thread on top of the pre-roll, before frames are dropped.
+const QUEUED_SECONDS: u32 = 2;
+/// Delay before trying again to record a channel whose file couldn't be created.
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+pub(crate) const MOTION_SUBDIRECTORY: &str = "motion";
+pub(crate) const CONTINUOUS_SUBDIRECTORY: &str = "continuous";
+const TIMELAPSE_SUBDIRECTORY: &str = "timelapse";
+/// Frames queued for the writer thread of a timelapse, which gets one per interval.
+const TIMELAPSE_QUEUE_CAPACITY: usize = 2;
+
+/// Where and how the video files of a channel are written.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct VideoFileSettings {
+    directory: PathBuf,
+    codec: String,
+    container: String,
+    file_name: String,
+    timestamp_format: String,
+}
+
+impl VideoFileSettings {
+    /// Settings of files written to the given subdirectory of the recording directory.
+    pub(crate) fn from_config(config: &RecordingConfig, subdirectory: &str) -> Self {
+        Self {
+            directory: config.directory.join(subdirectory),
+            codec: config.codec.clone(),
+            container: config.container.clone(),
+            file_name: config.file_name.clone(),
+            timestamp_format: config.timestamp_format.clone(),
+        }
+    }
+
+    /// Path of a file of the channel started now.
+    fn path(&self, channel_name: &str) -> PathBuf {
+        let file_name = self
+            .file_name
+            .replace("{channel}", &snapshot::to_file_name(channel_name))
+            .replace(
+                "{timestamp}",
+                &Local::now().format(&self.timestamp_format).to_string(),
+            );
+        self.directory
+            .join(format!("{}.{}", file_name, self.container))
+    }
+
+    fn fourcc(&self) -> VideoResult<i32> {
+        let mut codec = self.codec.chars();
+        match (codec.next(), codec.next(), codec.next(), codec.next()) {
+            (Some(c1), Some(c2), Some(c3), Some(c4)) => Ok(VideoWriter::fourcc(c1, c2, c3, c4)?),
+            _ => Err(VideoStreamError::VideoWriterError(
+                self.codec.clone(),
+                "not a FourCC code".to_string(),
+            )),
+        }
+    }
+}
+
+/// What is recorded from a channel.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ChannelRecordingSettings {
+    pub(crate) annotation: FrameAnnotation,
+    pub(crate) motion: Option<MotionRecordingSettings>,
+    pub(crate) continuous: Option<ContinuousRecordingSettings>,
+    pub(crate) timelapse: Option<TimelapseSettings>,
+}
+
+impl ChannelRecordingSettings {
+    pub(crate) fn from_config(config: &RecordingConfig, channel_name: &str) -> Self {
+        Self {
+            annotation: config.annotation,
+            motion: MotionRecordingSettings::from_config(config, channel_name),
+            continuous: ContinuousRecordingSettings::from_config(config, channel_name),
+            timelapse: TimelapseSettings::from_config(config, channel_name),
+        }
+    }
+}
+
+/// Settings of the motion recording of a channel.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MotionRecordingSettings {
+    files: VideoFileSettings,
+    pre_roll: Duration,
+    pre_roll_max_bytes: u64,
+    post_roll: Duration,
+}
+
+impl MotionRecordingSettings {
+    /// Returns None when motion isn't recorded on the channel.
+    pub(crate) fn from_config(config: &RecordingConfig, channel_name: &str) -> Option<Self> {
+        let motion = config.motion_for(channel_name)?;
+        Some(Self {
+            files: VideoFileSettings::from_config(config, MOTION_SUBDIRECTORY),
+            pre_roll: motion.pre_roll(),
+            pre_roll_max_bytes: motion.pre_roll_max_bytes(),
+            post_roll: motion.post_roll(),
+        })
+    }
+}
+
+/// Settings of the continuous recording of a channel.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ContinuousRecordingSettings {
+    files: VideoFileSettings,
+    segment_length: Duration,
+}
+
+impl ContinuousRecordingSettings {
+    /// Returns None when the channel isn't recorded continuously.
+    fn from_config(config: &RecordingConfig, channel_name: &str) -> Option<Self> {
+        let continuous = config.continuous_for(channel_name)?;
+        Some(Self {
+            files: VideoFileSettings::from_config(config, CONTINUOUS_SUBDIRECTORY),
+            segment_length: continuous.segment_length(),
+        })
+    }
+}
+
+/// Settings of the timelapse of a channel.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TimelapseSettings {
+    files: VideoFileSettings,
+    interval: Duration,
+    fps: f64,
+    timestamp: Option<OverlayConfig>,
+}
+
+impl TimelapseSettings {
+    /// Returns None when no timelapse is recorded of the channel.
+    fn from_config(config: &RecordingConfig, channel_name: &str) -> Option<Self> {
+        let timelapse = config.timelapse_for(channel_name)?;
+        Some(Self {
+            files: VideoFileSettings::from_config(config, TIMELAPSE_SUBDIRECTORY),
+            interval: timelapse.interval(),
+            fps: timelapse.fps as f64,
+            timestamp: timelapse.timestamp.clone(),
+        })
+    }
+}
+
+/// Video file encoded on its own thread, so that writing it doesn't slow down the
+/// capture. Frames are dropped when the encoder can't keep up.
+pub(crate) struct VideoFileWriter {
+    path: PathBuf,
+    sender: Option<SyncSender<Mat>>,
+    handle: Option<JoinHandle<()>>,
+    dropped_frames: u64,
+}
+
+impl VideoFileWriter {
+    /// Creates the file, and its directory when missing. Frames of another size than
+    /// `frame_size` are scaled to it.
+    pub(crate) fn create(
+        path: PathBuf,
+        settings: &VideoFileSettings,
+        fps: f64,
+        frame_size: Size,
+        queue_capacity: usize,
+    ) -> VideoResult<Self> {
+        let writer_error = |message: String| {
+            VideoStreamError::VideoWriterError(path.display().to_string(), message)
+        };
+
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory).map_err(|e| writer_error(e.to_string()))?;
+        }
+        let mut video_writer = VideoWriter::new(
+            &path.display().to_string(),
+            settings.fourcc()?,
+            fps,
+            frame_size,
+            true,
+        )?;
+        if !video_writer.is_opened()? {
+            return Err(writer_error(format!(
+                "codec \"{}\" or container \"{}\" is not supported",
+                settings.codec, settings.container
+            )));
+        }
+
+        let (sender, receiver) = mpsc::sync_channel::<Mat>(queue_capacity);
+        retention::add_active_file(&path);
+        let thread_path = path.clone();
+        let handle = thread::spawn(move || {
+            let mut failed = false;
+            for image in receiver {
+                if let Err(e) = write_frame(&mut video_writer, &image, frame_size) {
+                    if !failed {
+                        println!("Could not write to {}: {}", thread_path.display(), e);
+                        failed = true;
+                    }
+                }
+            }
+            match video_writer.release() {
+                Ok(()) => println!("Saved {}", thread_path.display()),
+                Err(e) => println!("Could not close {}: {}", thread_path.display(), e),
+            }
+            retention::remove_active_file(&thread_path);
+        });
+
+        Ok(Self {
+            path,
+            sender: Some(sender),
+            handle: Some(handle),
+            dropped_frames: 0,
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn write(&mut self, image: Mat) {
+        if let Some(sender) = &self.sender {
+            if let Err(TrySendError::Full(_)) = sender.try_send(image) {
+                self.dropped_frames += 1;
+            }
+        }
+    }
+
+    /// Closes the file once the queued frames are written, without waiting for it. The
+    /// encoder thread is handed to `closing_files`.
+    pub(crate) fn finish(mut self, closing_files: &ClosingFiles) {
+        self.sender = None;
+        if let Some(handle) = self.handle.take() {
+            closing_files.add(self.path.clone(), handle);
+        }
+        if self.dropped_frames > 0 {
+            println!(
+                "Dropped {} frame(s) of {}, the encoder was too slow",
+                self.dropped_frames,
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// Encoder threads of the files of a channel that are being closed. The capture waits
+/// for them only when it stops, so that no file is left unfinished.
+#[derive(Clone, Default)]
+pub(crate) struct ClosingFiles {
+    files: Arc<Mutex<Vec<ClosingFile>>>,
+}
+
+struct ClosingFile {
+    path: PathBuf,
+    handle: JoinHandle<()>,
+}
+
+impl ClosingFiles {
+    fn add(&self, path: PathBuf, handle: JoinHandle<()>) {
+        let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        let (closed, closing): (Vec<_>, Vec<_>) =
+            files.drain(..).partition(|file| file.handle.is_finished());
+        *files = closing;
+        files.push(ClosingFile { path, handle });
+        drop(files);
+
+        for file in closed {
+            file.join();
+        }
+    }
+
+    /// Waits for every file to be closed.
+    pub(crate) fn wait(&self) {
+        let files = std::mem::take(&mut *self.files.lock().unwrap_or_else(|e| e.into_inner()));
+        for file in files {
+            file.join();
+        }
+    }
+}
+
+impl ClosingFile {
+    fn join(self) {
+        if self.handle.join().is_err() {
+            println!("Writer of {} stopped with a panic", self.path.display());
+            retention::remove_active_file(&self.path);
+        }
+    }
+}
+
+fn write_frame(video_writer: &mut VideoWriter, image: &Mat, frame_size: Size) -> VideoResult<()> {
+    if image.size()? == frame_size {
+        video_writer.write(image)?;
+        return Ok(());
+    }
+
+    let mut scaled_image = Mat::default();
+    imgproc::resize(
+        image,
+        &mut scaled_image,
+        frame_size,
+        0.0,
+        0.0,
+        imgproc::INTER_AREA,
+    )?;
+    video_writer.write(&scaled_image)?;
+    Ok(())
+}
+
+/*
+The frames of the last `pre_roll` are kept in memory while nothing moves, as long as
+they take less than `pre_roll_max_bytes`. When motion starts, a clip is created with
+those frames first, and every following frame is added to it until nothing moved for
+`post_roll`.
+*/
+pub(crate) struct MotionRecorder {
+    channel_name: String,
+    settings: MotionRecordingSettings,
+    closing_files: ClosingFiles,
+    pre_roll_frames: VecDeque<ImageFrame>,
+    /// Memory taken by the images of `pre_roll_frames`.
+    pre_roll_bytes: u64,
+    clip: Option<MotionClip>,
+    retry_at: Option<Instant>,
+}
+
+struct MotionClip {
+    writer: VideoFileWriter,
+    last_motion: Instant,
+}
+
+impl MotionRecorder {
+    pub(crate) fn new(
+        channel_name: &str,
+        settings: MotionRecordingSettings,
+        closing_files: ClosingFiles,
+    ) -> Self {
+        Self {
+            channel_name: channel_name.to_string(),
+            settings,
+            closing_files,
+            pre_roll_frames: VecDeque::new(),
+            pre_roll_bytes: 0,
+            clip: None,
+            retry_at: None,
+        }
+    }
+
+    pub(crate) fn settings(&self) -> &MotionRecordingSettings {
+        &self.settings
+    }
+
+    /// Adds the frame to the current clip or to the pre-roll. `fps` is the frame rate
+    /// of the channel, used for new clips.
+    pub(crate) fn process(&mut self, frame: &ImageFrame, has_motion: bool, fps: f64) {
+        if let Some(clip) = &mut self.clip {
+            clip.writer.write(frame.image.clone());
+            if has_motion {
+                clip.last_motion = frame.instant;
+            } else if frame.instant.duration_since(clip.last_motion) >= self.settings.post_roll {
+                self.finish_clip();
+            }
+            return;
+        }
+
+        self.pre_roll_frames.push_back(frame.clone());
+        self.pre_roll_bytes += image_bytes(&frame.image);
+        while self.pre_roll_frames.front().is_some_and(|oldest| {
+            frame.instant.duration_since(oldest.instant) > self.settings.pre_roll
+                || self.pre_roll_bytes > self.settings.pre_roll_max_bytes
+        }) {
+            if let Some(oldest) = self.pre_roll_frames.pop_front() {
+                self.pre_roll_bytes -= image_bytes(&oldest.image);
+            }
+        }
+
+        let can_start = self
+            .retry_at
+            .is_none_or(|retry_at| Instant::now() >= retry_at);
+        if has_motion && can_start {
+            self.start_clip(frame, fps);
+        }
+    }
+
+    /// Closes the current clip, e.g. when the stream was lost or the capture stops.
+    pub(crate) fn stop(&mut self) {
+        self.finish_clip();
+        self.clear_pre_roll();
+    }
+
+    fn start_clip(&mut self, frame: &ImageFrame, fps: f64) {
+        let path = self.settings.files.path(&self.channel_name);
+        let pre_roll_count = self.pre_roll_frames.len();
+        let queue_capacity =
+            pre_roll_count + (fps.ceil() as usize).max(1) * QUEUED_SECONDS as usize;
+
+        let writer = frame
+            .image
+            .size()
+            .map_err(VideoStreamError::from)
+            .and_then(|frame_size| {
+                VideoFileWriter::create(path, &self.settings.files, fps, frame_size, queue_capacity)
+            });
+        let mut writer = match writer {
+            Ok(writer) => writer,
+            Err(e) => {
+                println!("Could not record camera {}: {}", self.channel_name, e);
+                self.clear_pre_roll();
+                self.retry_at = Some(Instant::now() + RETRY_DELAY);
+                return;
+            }
+        };
+
+        println!(
+            "Recording motion on camera {} to {}",
+            self.channel_name,
+            writer.path().display()
+        );
+        for pre_roll_frame in self.pre_roll_frames.drain(..) {
+            writer.write(pre_roll_frame.image);
+        }
+        self.pre_roll_bytes = 0;
+        self.retry_at = None;
+        self.clip = Some(MotionClip {
+            writer,
+            last_motion: frame.instant,
+        });
+    }
+
+    fn clear_pre_roll(&mut self) {
+        self.pre_roll_frames.clear();
+        self.pre_roll_bytes = 0;
+    }
+
+    fn finish_clip(&mut self) {
+        if let Some(clip) = self.clip.take() {
+            clip.writer.finish(&self.closing_files);
+        }
+    }
+}
+
+fn image_bytes(image: &Mat) -> u64 {
+    (image.total() * image.elem_size().unwrap_or(0)) as u64
+}
+
+/// Writes every frame of a channel to files of a fixed length, named by the time they
+/// start.
+pub(crate) struct SegmentRecorder {
+    channel_name: String,
+    settings: ContinuousRecordingSettings,
+    closing_files: ClosingFiles,
+    segment: Option<Segment>,
+    retry_at: Option<Instant>,
+}
+
+struct Segment {
+    writer: VideoFileWriter,
+    started: Instant,
+}
+
+impl SegmentRecorder {
+    pub(crate) fn new(
+        channel_name: &str,
+        settings: ContinuousRecordingSettings,
+        closing_files: ClosingFiles,
+    ) -> Self {
+        Self {
+            channel_name: channel_name.to_string(),
+            settings,
+            closing_files,
+            segment: None,
+            retry_at: None,
+        }
+    }
+
+    pub(crate) fn settings(&self) -> &ContinuousRecordingSettings {
+        &self.settings
+    }
+
+    /// Adds the frame to the current segment, starting a new one when it is full.
+    pub(crate) fn process(&mut self, frame: &ImageFrame, fps: f64) {
+        let segment_full = self.segment.as_ref().is_some_and(|segment| {
+            frame.instant.duration_since(segment.started) >= self.settings.segment_length
+        });
+        if segment_full {
+            self.finish_segment();
+        }
+
+        if self.segment.is_none() {
+            if self
+                .retry_at
+                .is_some_and(|retry_at| Instant::now() < retry_at)
+            {
+                return;
+            }
+            self.start_segment(frame, fps);
+        }
+        if let Some(segment) = &mut self.segment {
+            segment.writer.write(frame.image.clone());
+        }
+    }
+
+    /// Closes the current segment, e.g. when the stream was lost or the capture stops.
+    /// The next frame starts a new one.
+    pub(crate) fn stop(&mut self) {
+        self.finish_segment();
+    }
+
+    fn start_segment(&mut self, frame: &ImageFrame, fps: f64) {
+        let path = self.settings.files.path(&self.channel_name);
+        let queue_capacity = (fps.ceil() as usize).max(1) * QUEUED_SECONDS as usize;
+
+        let writer = frame
+            .image
+            .size()
+            .map_err(VideoStreamError::from)
+            .and_then(|frame_size| {
+                VideoFileWriter::create(path, &self.settings.files, fps, frame_size, queue_capacity)
+            });
+        match writer {
+            Ok(writer) => {
+                println!(
+                    "Recording camera {} to {}",
+                    self.channel_name,
+                    writer.path().display()
+                );
+                self.retry_at = None;
+                self.segment = Some(Segment {
+                    writer,
+                    started: frame.instant,
+                });
+            }
+            Err(e) => {
+                println!("Could not record camera {}: {}", self.channel_name, e);
+                self.retry_at = Some(Instant::now() + RETRY_DELAY);
+            }
+        }
+    }
+
+    fn finish_segment(&mut self) {
+        if let Some(segment) = self.segment.take() {
+            segment.writer.finish(&self.closing_files);
+        }
+    }
+}
+
+/*
+A frame is taken every `interval` and added to the file of the current day, which is
+played at its own frame rate. A new file is started at midnight, local time, and when
+the capture starts again, as a video file can't be reopened to add frames to it.
+*/
+pub(crate) struct TimelapseRecorder {
+    channel_name: String,
+    settings: TimelapseSettings,
+    /// Time overlay drawn over the frames, created from the settings.
+    timestamp: Option<Overlay>,
+    closing_files: ClosingFiles,
+    file: Option<TimelapseFile>,
+    last_frame: Option<Instant>,
+    retry_at: Option<Instant>,
+}
+
+struct TimelapseFile {
+    writer: VideoFileWriter,
+    day: NaiveDate,
+}
+
+impl TimelapseRecorder {
+    pub(crate) fn new(
+        channel_name: &str,
+        settings: TimelapseSettings,
+        closing_files: ClosingFiles,
+    ) -> Self {
+        Self {
+            channel_name: channel_name.to_string(),
+            timestamp: settings
+                .timestamp
+                .as_ref()
+                .map(overlay_text_providers::create_overlay),
+            settings,
+            closing_files,
+            file: None,
+            last_frame: None,
+            retry_at: None,
+        }
+    }
+
+    pub(crate) fn settings(&self) -> &TimelapseSettings {
+        &self.settings
+    }
+
+    /// Adds the frame to the file of the day when the interval has passed since the
+    /// last one.
+    pub(crate) fn process(&mut self, frame: &ImageFrame) {
+        let is_due = self.last_frame.is_none_or(|last_frame| {
+            frame.instant.duration_since(last_frame) >= self.settings.interval
+        });
+        if !is_due {
+            return;
+        }
+
+        let today = Local::now().date_naive();
+        if self.file.as_ref().is_some_and(|file| file.day != today) {
+            self.finish_file();
+        }
+        if self.file.is_none() {
+            if self
+                .retry_at
+                .is_some_and(|retry_at| Instant::now() < retry_at)
+            {
+                return;
+            }
+            self.start_file(frame, today);
+        }
+
+        let mut image = frame.image.clone();
+        if let Err(e) = self.draw_timestamp(&mut image) {
+            println!(
+                "Could not draw the timestamp of the timelapse of camera {}: {}",
+                self.channel_name, e
+            );
+        }
+        if let Some(file) = &mut self.file {
+            file.writer.write(image);
+            self.last_frame = Some(frame.instant);
+        }
+    }
+
+    /// Closes the file of the day when the capture stops.
+    pub(crate) fn stop(&mut self) {
+        self.finish_file();
+    }
+
+    fn draw_timestamp(&self, image: &mut Mat) -> VideoResult<()> {
+        let Some(timestamp) = &self.timestamp else {
+            return Ok(());
+        };
+        let line = TextLine {
+            text: timestamp.provider.get_text(),
+            style: &timestamp.style,
+            color: timestamp.style.color,
+            renderer: timestamp.renderer.as_ref(),
+        };
+        text_layout::draw_text_lines(image, &[line], |_| 0)
+    }
+
+    fn start_file(&mut self, frame: &ImageFrame, day: NaiveDate) {
+        let path = self.settings.files.path(&self.channel_name);
+        let writer = frame
+            .image
+            .size()
+            .map_err(VideoStreamError::from)
+            .and_then(|frame_size| {
+                VideoFileWriter::create(
+                    path,
+                    &self.settings.files,
+                    self.settings.fps,
+                    frame_size,
+                    TIMELAPSE_QUEUE_CAPACITY,
+                )
+            });
+        match writer {
+            Ok(writer) => {
+                println!(
+                    "Recording the timelapse of camera {} to {}",
+                    self.channel_name,
+                    writer.path().display()
+                );
+                self.retry_at = None;
+                self.file = Some(TimelapseFile { writer, day });
+            }
+            Err(e) => {
+                println!(
+                    "Could not record the timelapse of camera {}: {}",
+                    self.channel_name, e
+                );
+                self.retry_at = Some(Instant::now() + RETRY_DELAY);
+            }
+        }
+    }
+
+    fn finish_file(&mut self) {
+        if let Some(file) = self.file.take() {
+            file.writer.finish(&self.closing_files);
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use crate::config::RecordingConfig;
+use crate::video_display::recording::{CONTINUOUS_SUBDIRECTORY, MOTION_SUBDIRECTORY};
+use std::collections::BTreeSet;
+use std::ffi::{CString, OsStr};
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+pub(crate) const RETENTION_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Files modified more recently than this may still be written by another process and
+/// are kept.
+const ACTIVE_FILE_AGE: Duration = Duration::from_secs(60);
+
+/// Alert about the disk of the recordings, shown on the display while it lasts.
+pub(crate) type DiskAlert = Arc<Mutex<Option<String>>>;
+
+/*
+Files being recorded, from their creation until their encoder closed them. They are
+never deleted, whatever their age: a timelapse keeps the file of the day open, and its
+modification time can be older than `ACTIVE_FILE_AGE` between two frames. The files are
+added by the capture threads of every channel, so the set is kept for the whole process
+instead of being handed to each of them.
+*/
+static ACTIVE_FILES: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+pub(crate) fn add_active_file(path: &Path) {
+    active_files().insert(path.to_path_buf());
+}
+
+pub(crate) fn remove_active_file(path: &Path) {
+    active_files().remove(path);
+}
+
+fn active_files() -> MutexGuard<'static, BTreeSet<PathBuf>> {
+    ACTIVE_FILES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Deletes the oldest recordings when they are too old or take too much space, and
+/// raises an alert when the disk is running out of space. Only the motion clips and the
+/// continuous segments are deleted: timelapses are small and each covers a whole day,
+/// and any other file in the recording directory isn't a recording.
+pub(crate) struct RetentionManager {
+    directory: PathBuf,
+    /// Extension of the recordings, the only files that are deleted.
+    container: String,
+    max_age: Option<Duration>,
+    max_size_bytes: Option<u64>,
+    min_free_space_bytes: u64,
+    disk_alert: DiskAlert,
+}
+
+struct RecordedFile {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+}
+
+impl RetentionManager {
+    /// Returns None when nothing is recorded.
+    pub(crate) fn from_config(config: &RecordingConfig, disk_alert: DiskAlert) -> Option<Self> {
+        if !config.is_enabled() {
+            return None;
+        }
+        Some(Self {
+            directory: config.directory.clone(),
+            container: config.container.clone(),
+            max_age: config.retention.max_age(),
+            max_size_bytes: config.retention.max_size_bytes(),
+            min_free_space_bytes: config.retention.min_free_space_bytes(),
+            disk_alert,
+        })
+    }
+
+    /// Applies the limits once. Errors are logged, so a full or missing disk never stops
+    /// the display.
+    pub(crate) fn run(&mut self) {
+        match self.list_recorded_files() {
+            Ok(files) => self.delete_old_files(files),
+            Err(e) => println!(
+                "Could not list the recordings in {}: {}",
+                self.directory.display(),
+                e
+            ),
+        }
+        self.check_free_space();
+    }
+
+    /*
+    Files are deleted from the oldest, first those older than the maximum age, then
+    while the total size is above the limit. Files that are still being written are
+    never deleted, even if that leaves the directory above its limit.
+    */
+    fn delete_old_files(&self, mut files: Vec<RecordedFile>) {
+        files.sort_by_key(|file| file.modified);
+        let mut total_size: u64 = files.iter().map(|file| file.size).sum();
+        let now = SystemTime::now();
+
+        let active_files = active_files().clone();
+
+        for file in files {
+            if active_files.contains(&file.path) {
+                continue;
+            }
+            let age = now.duration_since(file.modified).unwrap_or_default();
+            if age < ACTIVE_FILE_AGE {
+                break;
+            }
+            let too_old = self.max_age.is_some_and(|max_age| age > max_age);
+            let too_large = self
+                .max_size_bytes
+                .is_some_and(|max_size| total_size > max_size);
+            if !too_old && !too_large {
+                break;
+            }
+
+            match fs::remove_file(&file.path) {
+                Ok(()) => {
+                    println!("Deleted recording {}", file.path.display());
+                    total_size -= file.size;
+                }
+                Err(e) => println!("Could not delete {}: {}", file.path.display(), e),
+            }
+        }
+    }
+
+    /// Motion clips and continuous segments, in the subdirectories that exist.
+    fn list_recorded_files(&self) -> io::Result<Vec<RecordedFile>> {
+        let mut files = Vec::new();
+        for subdirectory in [MOTION_SUBDIRECTORY, CONTINUOUS_SUBDIRECTORY] {
+            let directory = self.directory.join(subdirectory);
+            if directory.exists() {
+                list_files(&directory, OsStr::new(&self.container), &mut files)?;
+            }
+        }
+        Ok(files)
+    }
+
+    fn check_free_space(&self) {
+        let alert = match free_space(existing_directory(&self.directory)) {
+            Ok(free_bytes) if free_bytes < self.min_free_space_bytes => Some(format!(
+                "Low disk space: {} MB free for recordings",
+                free_bytes / (1024 * 1024)
+            )),
+            Ok(_) => None,
+            Err(e) => Some(format!("Could not check the recording disk: {}", e)),
+        };
+
+        let mut disk_alert = self.disk_alert.lock().unwrap_or_else(|e| e.into_inner());
+        if *disk_alert != alert {
+            match &alert {
+                Some(alert) => println!("{}", alert),
+                None => println!("Disk space for recordings is back above the limit"),
+            }
+            *disk_alert = alert;
+        }
+    }
+}
+
+/// Adds the files with the extension in the directory and in its subdirectories, as the
+/// file name of the recordings may contain a path.
+fn list_files(
+    directory: &Path,
+    extension: &OsStr,
+    files: &mut Vec<RecordedFile>,
+) -> io::Result<()> {
+    let mut directories = vec![directory.to_path_buf()];
+
+    while let Some(directory) = directories.pop() {
+        for entry in fs::read_dir(&directory)? {
+            let entry = entry?;
+            let path = entry.path();
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                directories.push(path);
+            } else if metadata.is_file() && path.extension() == Some(extension) {
+                files.push(RecordedFile {
+                    path,
+                    size: metadata.len(),
+                    modified: metadata.modified()?,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The directory, or its closest parent when it wasn't created yet, which is on the disk
+/// the recordings will be written to.
+fn existing_directory(directory: &Path) -> &Path {
+    directory
+        .ancestors()
+        .find(|path| !path.as_os_str().is_empty() && path.exists())
+        .unwrap_or(Path::new("."))
+}
+
+/// Space available to this user on the disk of the path, in bytes.
+fn free_space(path: &Path) -> io::Result<u64> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    /// Empty recording directory for a test, removed when the test is done.
+    struct TestDirectory(PathBuf);
+
+    impl TestDirectory {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("omni_pane_{}_{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        /// Creates a file of `size` bytes last modified `age` ago.
+        fn add_file(&self, name: &str, size: u64, age: Duration) -> PathBuf {
+            let path = self.0.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            let file = File::create(&path).unwrap();
+            file.set_len(size).unwrap();
+            file.set_modified(SystemTime::now() - age).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TestDirectory {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn retention_manager(
+        directory: &TestDirectory,
+        max_age: Option<Duration>,
+        max_size_bytes: Option<u64>,
+    ) -> RetentionManager {
+        RetentionManager {
+            directory: directory.0.clone(),
+            container: "mp4".to_string(),
+            max_age,
+            max_size_bytes,
+            min_free_space_bytes: 0,
+            disk_alert: DiskAlert::default(),
+        }
+    }
+
+    #[test]
+    fn deletes_files_older_than_max_age() {
+        let directory = TestDirectory::new("retention_max_age");
+        let old = directory.add_file("motion/old.mp4", 100, 3 * HOUR);
+        let recent = directory.add_file("motion/recent.mp4", 100, HOUR);
+
+        retention_manager(&directory, Some(2 * HOUR), None).run();
+
+        assert!(!old.exists());
+        assert!(recent.exists());
+    }
+
+    #[test]
+    fn deletes_oldest_files_until_under_max_size() {
+        let directory = TestDirectory::new("retention_max_size");
+        let oldest = directory.add_file("continuous/oldest.mp4", 100, 4 * HOUR);
+        let older = directory.add_file("motion/older.mp4", 100, 3 * HOUR);
+        let old = directory.add_file("continuous/old.mp4", 100, 2 * HOUR);
+        let recent = directory.add_file("motion/recent.mp4", 100, HOUR);
+
+        retention_manager(&directory, None, Some(250)).run();
+
+        assert!(!oldest.exists());
+        assert!(!older.exists());
+        assert!(old.exists());
+        assert!(recent.exists());
+    }
+
+    #[test]
+    fn keeps_files_being_recorded() {
+        let directory = TestDirectory::new("retention_active");
+        let active = directory.add_file("motion/active.mp4", 100, 3 * HOUR);
+        let finished = directory.add_file("motion/finished.mp4", 100, 2 * HOUR);
+        let just_modified = directory.add_file("motion/just_modified.mp4", 100, Duration::ZERO);
+
+        add_active_file(&active);
+        retention_manager(&directory, Some(HOUR), Some(0)).run();
+        remove_active_file(&active);
+
+        assert!(active.exists());
+        assert!(!finished.exists());
+        assert!(just_modified.exists());
+    }
+
+    #[test]
+    fn keeps_files_that_are_not_clips_or_segments() {
+        let directory = TestDirectory::new("retention_other_files");
+        let timelapse = directory.add_file("timelapse/day.mp4", 100, 3 * HOUR);
+        let snapshot = directory.add_file("motion/snapshot.png", 100, 3 * HOUR);
+        let config = directory.add_file("config.toml", 100, 3 * HOUR);
+        let top_level = directory.add_file("clip.mp4", 100, 3 * HOUR);
+        let clip = directory.add_file("motion/camera/clip.mp4", 100, 3 * HOUR);
+
+        retention_manager(&directory, Some(HOUR), None).run();
+
+        assert!(timelapse.exists());
+        assert!(snapshot.exists());
+        assert!(config.exists());
+        assert!(top_level.exists());
+        assert!(!clip.exists());
+    }
+}
@@ -0,0 +1,311 @@
+use crate::config::{FrameAnnotation, ImageFormat, SnapshotConfig};
+use crate::video_display::motion_detection::MotionState;
+use crate::video_display::{ImageFrame, VideoResult, VideoStreamError};
+use chrono::Local;
+use opencv::core::{Mat, Vector};
+use opencv::imgcodecs;
+use serde::Serialize;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// What a snapshot was taken for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SnapshotTrigger {
+    Key,
+    Motion,
+    Interval,
+}
+
+impl SnapshotTrigger {
+    fn name(&self) -> &'static str {
+        match self {
+            SnapshotTrigger::Key => "key",
+            SnapshotTrigger::Motion => "motion",
+            SnapshotTrigger::Interval => "interval",
+        }
+    }
+}
+
+/// Where and how the snapshot images are written.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SnapshotFileSettings {
+    directory: PathBuf,
+    format: ImageFormat,
+    jpeg_quality: u8,
+    png_compression: u8,
+    file_name: String,
+    timestamp_format: String,
+    events_file: Option<PathBuf>,
+}
+
+/// Line written to the events file for every snapshot.
+#[derive(Serialize)]
+struct SnapshotEvent<'a> {
+    event: &'static str,
+    time: String,
+    channel: &'a str,
+    trigger: SnapshotTrigger,
+    path: &'a Path,
+}
+
+impl SnapshotFileSettings {
+    pub(crate) fn from_config(config: &SnapshotConfig) -> Self {
+        Self {
+            directory: config.directory.clone(),
+            format: config.format,
+            jpeg_quality: config.jpeg_quality,
+            png_compression: config.png_compression,
+            file_name: config.file_name.clone(),
+            timestamp_format: config.timestamp_format.clone(),
+            events_file: config.events_file.clone(),
+        }
+    }
+
+    /// Path of a snapshot of the channel taken now.
+    fn path(&self, channel_name: &str, trigger: SnapshotTrigger) -> PathBuf {
+        let file_name = self
+            .file_name
+            .replace("{channel}", &to_file_name(channel_name))
+            .replace("{trigger}", trigger.name())
+            .replace(
+                "{timestamp}",
+                &Local::now().format(&self.timestamp_format).to_string(),
+            );
+        self.directory
+            .join(format!("{}.{}", file_name, self.format.extension()))
+    }
+
+    fn encoding_parameters(&self) -> Vector<i32> {
+        let parameters = match self.format {
+            ImageFormat::Jpeg => [imgcodecs::IMWRITE_JPEG_QUALITY, self.jpeg_quality as i32],
+            ImageFormat::Png => [
+                imgcodecs::IMWRITE_PNG_COMPRESSION,
+                self.png_compression as i32,
+            ],
+        };
+        Vector::from_slice(&parameters)
+    }
+
+    /// Appends the event of the snapshot to the events file, when there is one.
+    fn send_event(&self, event: &SnapshotEvent) -> io::Result<()> {
+        let Some(events_file) = &self.events_file else {
+            return Ok(());
+        };
+        let mut line = serde_json::to_string(event)?;
+        line.push('\n');
+
+        /*
+        The whole line is written at once to a file opened in append mode, so the
+        events of the capture threads and of the display never mix.
+        */
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(events_file)?;
+        file.write_all(line.as_bytes())
+    }
+}
+
+/// Saves the image of the channel, logs its path and sends the snapshot event.
+pub(crate) fn save_snapshot(
+    image: &Mat,
+    channel_name: &str,
+    trigger: SnapshotTrigger,
+    settings: &SnapshotFileSettings,
+) -> VideoResult<PathBuf> {
+    fs::create_dir_all(&settings.directory).map_err(|e| {
+        VideoStreamError::WriteImageError(format!("{}: {}", settings.directory.display(), e))
+    })?;
+    let path = settings.path(channel_name, trigger);
+
+    let path_text = path.display().to_string();
+    if !imgcodecs::imwrite(&path_text, image, &settings.encoding_parameters())? {
+        return Err(VideoStreamError::WriteImageError(path_text));
+    }
+    println!(
+        "Snapshot of camera {} ({}) saved to {}",
+        channel_name,
+        trigger.name(),
+        path_text
+    );
+
+    let event = SnapshotEvent {
+        event: "snapshot",
+        time: Local::now().to_rfc3339(),
+        channel: channel_name,
+        trigger,
+        path: &path,
+    };
+    if let Err(e) = settings.send_event(&event) {
+        println!("Could not send the snapshot event of {}: {}", path_text, e);
+    }
+    Ok(path)
+}
+
+/// Snapshots taken of a channel by its capture thread.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ChannelSnapshotSettings {
+    pub(crate) files: SnapshotFileSettings,
+    pub(crate) annotation: FrameAnnotation,
+    /// Time in which the best frame of a motion event is chosen, None when no
+    /// snapshot is taken on motion.
+    pub(crate) best_frame_window: Option<Duration>,
+    /// None when no snapshot is taken on an interval.
+    pub(crate) interval: Option<Duration>,
+}
+
+impl ChannelSnapshotSettings {
+    pub(crate) fn from_config(config: &SnapshotConfig, channel_name: &str) -> Self {
+        Self {
+            files: SnapshotFileSettings::from_config(config),
+            annotation: config.annotation,
+            best_frame_window: config
+                .motion_for(channel_name)
+                .map(|motion| motion.best_frame_window()),
+            interval: config
+                .interval_for(channel_name)
+                .map(|interval| interval.interval()),
+        }
+    }
+}
+
+/// Frame chosen for a snapshot, with the motion that was found on it.
+pub(crate) struct SnapshotFrame {
+    pub(crate) trigger: SnapshotTrigger,
+    pub(crate) frame: ImageFrame,
+    pub(crate) motion: MotionState,
+}
+
+/// Chooses the frames of a channel that are saved as snapshots.
+pub(crate) struct Snapshotter {
+    settings: ChannelSnapshotSettings,
+    motion_event: Option<MotionEvent>,
+    last_interval_snapshot: Option<Instant>,
+}
+
+/// Motion event whose snapshot is being chosen.
+struct MotionEvent {
+    start: Instant,
+    best_frame: Option<(ImageFrame, MotionState)>,
+    /// Set once the snapshot of the event was taken, until the motion stops.
+    done: bool,
+}
+
+impl Snapshotter {
+    pub(crate) fn new(settings: ChannelSnapshotSettings) -> Self {
+        Self {
+            settings,
+            motion_event: None,
+            last_interval_snapshot: None,
+        }
+    }
+
+    pub(crate) fn settings(&self) -> &ChannelSnapshotSettings {
+        &self.settings
+    }
+
+    /// Returns the frames to save. `checked_motion` is the result of the movement check
+    /// done on this frame, None if there was none, and `motion` the latest result.
+    pub(crate) fn process(
+        &mut self,
+        frame: &ImageFrame,
+        checked_motion: Option<&MotionState>,
+        motion: &MotionState,
+    ) -> Vec<SnapshotFrame> {
+        let mut snapshots = Vec::new();
+
+        if let Some(window) = self.settings.best_frame_window {
+            if let Some(snapshot) = self.process_motion(frame, checked_motion, window) {
+                snapshots.push(snapshot);
+            }
+        }
+
+        if let Some(interval) = self.settings.interval {
+            let is_due = self.last_interval_snapshot.is_none_or(|last_snapshot| {
+                frame.instant.duration_since(last_snapshot) >= interval
+            });
+            if is_due {
+                self.last_interval_snapshot = Some(frame.instant);
+                snapshots.push(SnapshotFrame {
+                    trigger: SnapshotTrigger::Interval,
+                    frame: frame.clone(),
+                    motion: motion.clone(),
+                });
+            }
+        }
+
+        snapshots
+    }
+
+    /*
+    The frame with the largest moving area among the ones checked in the window after
+    the motion started is kept, and saved when the window ends or when the motion stops
+    before that. Only the checked frames are compared, as the boxes of a check don't
+    match the frames read after it.
+    */
+    fn process_motion(
+        &mut self,
+        frame: &ImageFrame,
+        checked_motion: Option<&MotionState>,
+        window: Duration,
+    ) -> Option<SnapshotFrame> {
+        if let Some(motion) = checked_motion {
+            if motion.has_motion() {
+                let motion_event = self.motion_event.get_or_insert(MotionEvent {
+                    start: frame.instant,
+                    best_frame: None,
+                    done: false,
+                });
+                let is_best = motion_event
+                    .best_frame
+                    .as_ref()
+                    .is_none_or(|(_, best_motion)| motion.moving_area > best_motion.moving_area);
+                if !motion_event.done && is_best {
+                    motion_event.best_frame = Some((frame.clone(), motion.clone()));
+                }
+            } else if let Some(mut motion_event) = self.motion_event.take() {
+                return motion_event.take_snapshot();
+            }
+        }
+
+        let motion_event = self.motion_event.as_mut()?;
+        if frame.instant.duration_since(motion_event.start) >= window {
+            motion_event.take_snapshot()
+        } else {
+            None
+        }
+    }
+
+    /// Forgets the motion event in progress, e.g. when the stream was lost.
+    pub(crate) fn reset(&mut self) {
+        self.motion_event = None;
+    }
+}
+
+impl MotionEvent {
+    fn take_snapshot(&mut self) -> Option<SnapshotFrame> {
+        if self.done {
+            return None;
+        }
+        self.done = true;
+        let (frame, motion) = self.best_frame.take()?;
+        Some(SnapshotFrame {
+            trigger: SnapshotTrigger::Motion,
+            frame,
+            motion,
+        })
+    }
+}
+
+/// Replaces the characters of the channel name that could cause trouble in a file name.
+pub(crate) fn to_file_name(channel_name: &str) -> String {
+    channel_name
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect()
+}
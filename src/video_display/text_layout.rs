@@ -0,0 +1,194 @@
+use crate::config::{BackgroundConfig, OverlayStyle};
+use crate::video_display::image_manipulation;
+use crate::video_display::image_manipulation::TextPosition;
+use crate::video_display::text_renderer::TextRenderer;
+use crate::video_display::VideoResult;
+use opencv::core::{Mat, MatTraitConst, Point, Rect, Size};
+use std::sync::Arc;
+
+/// Distance between the text and the edges of the image.
+const EDGE_MARGIN: i32 = 20;
+const LINE_SPACING: i32 = 8;
+const ANCHORS: [TextPosition; 5] = [
+    TextPosition::TopLeft,
+    TextPosition::TopRight,
+    TextPosition::BottomLeft,
+    TextPosition::BottomRight,
+    TextPosition::Center,
+];
+
+/// Line of text drawn over the display with its own style.
+pub(crate) struct TextLine<'a> {
+    pub(crate) text: String,
+    pub(crate) style: &'a OverlayStyle,
+    /// Text colour, which is not the one of the style when the data is stale.
+    pub(crate) color: [u8; 3],
+    pub(crate) renderer: &'a dyn TextRenderer,
+}
+
+/// Line of text of an overlay, owned so it can be handed to the capture threads and
+/// drawn over the recordings as well.
+#[derive(Clone)]
+pub(crate) struct OverlayText {
+    pub(crate) text: String,
+    pub(crate) style: OverlayStyle,
+    pub(crate) color: [u8; 3],
+    pub(crate) renderer: Arc<dyn TextRenderer>,
+}
+
+impl OverlayText {
+    pub(crate) fn line(&self) -> TextLine<'_> {
+        TextLine {
+            text: self.text.clone(),
+            style: &self.style,
+            color: self.color,
+            renderer: self.renderer.as_ref(),
+        }
+    }
+}
+
+struct PlacedLine<'a> {
+    line: &'a TextLine<'a>,
+    text_size: Size,
+    baseline: i32,
+    padding: i32,
+}
+
+impl PlacedLine<'_> {
+    fn box_size(&self) -> Size {
+        Size::new(
+            self.text_size.width + 2 * self.padding,
+            self.text_size.height + self.baseline + 2 * self.padding,
+        )
+    }
+}
+
+/// Draws the lines stacked at their anchors. `edge_offset` tells how far the block of
+/// an anchor has to be moved away from its top or bottom edge.
+pub(crate) fn draw_text_lines(
+    image: &mut Mat,
+    lines: &[TextLine],
+    edge_offset: impl Fn(TextPosition) -> i32,
+) -> VideoResult<()> {
+    let image_size = image.size()?;
+
+    /*
+    The lines of every anchor are measured first, so each anchor can be laid out as one
+    block: top blocks grow down from the top edge, bottom blocks end at the bottom edge
+    and the centre block is centred on both axes. Within a block the lines keep the
+    order in which they were given.
+    */
+    for anchor in ANCHORS {
+        let mut placed_lines = Vec::new();
+        for line in lines.iter().filter(|line| line.style.anchor == anchor) {
+            placed_lines.push(measure_line(line)?);
+        }
+        if placed_lines.is_empty() {
+            continue;
+        }
+
+        let block_height: i32 = placed_lines
+            .iter()
+            .map(|placed_line| placed_line.box_size().height + LINE_SPACING)
+            .sum::<i32>()
+            - LINE_SPACING;
+
+        let mut y = match anchor {
+            TextPosition::TopLeft | TextPosition::TopRight => EDGE_MARGIN + edge_offset(anchor),
+            TextPosition::BottomLeft | TextPosition::BottomRight => {
+                image_size.height - EDGE_MARGIN - edge_offset(anchor) - block_height
+            }
+            TextPosition::Center => (image_size.height - block_height) / 2,
+        };
+
+        for placed_line in &placed_lines {
+            let box_size = placed_line.box_size();
+            let x = match anchor {
+                TextPosition::TopLeft | TextPosition::BottomLeft => EDGE_MARGIN,
+                TextPosition::TopRight | TextPosition::BottomRight => {
+                    image_size.width - EDGE_MARGIN - box_size.width
+                }
+                TextPosition::Center => (image_size.width - box_size.width) / 2,
+            };
+
+            draw_line(
+                image,
+                placed_line,
+                Rect::new(x, y, box_size.width, box_size.height),
+            )?;
+            y += box_size.height + LINE_SPACING;
+        }
+    }
+
+    Ok(())
+}
+
+fn measure_line<'a>(line: &'a TextLine<'a>) -> VideoResult<PlacedLine<'a>> {
+    let (text_size, baseline) = line.renderer.measure(&line.text)?;
+    let padding = line
+        .style
+        .background
+        .as_ref()
+        .map_or(0, |background| background.padding as i32);
+
+    Ok(PlacedLine {
+        line,
+        text_size,
+        baseline,
+        padding,
+    })
+}
+
+fn draw_line(image: &mut Mat, placed_line: &PlacedLine, area: Rect) -> VideoResult<()> {
+    let style = placed_line.line.style;
+    if let Some(background) = &style.background {
+        draw_background(image, area, background)?;
+    }
+
+    let origin = Point::new(
+        area.x + placed_line.padding,
+        area.y + placed_line.padding + placed_line.text_size.height,
+    );
+    placed_line.line.renderer.draw(
+        image,
+        &placed_line.line.text,
+        origin,
+        image_manipulation::to_scalar(placed_line.line.color),
+    )
+}
+
+/// Blends the background colour into the area, clipped to the image.
+fn draw_background(image: &mut Mat, area: Rect, background: &BackgroundConfig) -> VideoResult<()> {
+    let image_size = image.size()?;
+    let x = area.x.max(0);
+    let y = area.y.max(0);
+    let right = (area.x + area.width).min(image_size.width);
+    let bottom = (area.y + area.height).min(image_size.height);
+    if right <= x || bottom <= y {
+        return Ok(());
+    }
+    let area = Rect::new(x, y, right - x, bottom - y);
+
+    let mut blended = Mat::default();
+    {
+        let target = Mat::roi(image, area)?;
+        let fill = Mat::new_size_with_default(
+            Size::new(area.width, area.height),
+            target.typ(),
+            image_manipulation::to_scalar(background.color),
+        )?;
+        opencv::core::add_weighted(
+            &target,
+            1.0 - background.opacity,
+            &fill,
+            background.opacity,
+            0.0,
+            &mut blended,
+            -1,
+        )?;
+    }
+
+    let mut target = Mat::roi_mut(image, area)?;
+    blended.copy_to(&mut target)?;
+    Ok(())
+}
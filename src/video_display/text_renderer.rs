@@ -0,0 +1,231 @@
+use crate::config::{OverlayFont, OverlayStyle};
+use crate::video_display::{VideoResult, VideoStreamError};
+use ab_glyph::{Font, FontRef, GlyphId, PxScale, ScaleFont};
+use opencv::core::{Mat, MatTrait, MatTraitConst, Point, Scalar, Size, Vec3b, CV_8UC3};
+use opencv::imgproc;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Height in pixels of the text drawn with a font file at scale 1.
+const TRUETYPE_BASE_SIZE: f32 = 32.0;
+
+/// Draws lines of text on an image, from any thread.
+pub(crate) trait TextRenderer: Send + Sync {
+    /// Returns the size of the text above the baseline and how far it goes below it.
+    fn measure(&self, text: &str) -> VideoResult<(Size, i32)>;
+
+    /// Draws the text with its baseline starting at `origin`.
+    fn draw(&self, image: &mut Mat, text: &str, origin: Point, color: Scalar) -> VideoResult<()>;
+}
+
+/// Renderer of the style: the font file when one is configured, or else the built-in
+/// Hershey font. A font file that can't be loaded falls back to the Hershey font.
+pub(crate) fn create_renderer(style: &OverlayStyle) -> Box<dyn TextRenderer> {
+    if let Some(font_file) = &style.font_file {
+        match TrueTypeFont::load(font_file) {
+            Ok(font) => {
+                return Box::new(TrueTypeRenderer {
+                    font,
+                    scale: PxScale::from(TRUETYPE_BASE_SIZE * style.scale as f32),
+                });
+            }
+            Err(e) => println!("{}, using the built-in font", e),
+        }
+    }
+
+    Box::new(HersheyRenderer {
+        font_face: font_face(style.font),
+        scale: style.scale,
+        thickness: style.thickness as i32,
+    })
+}
+
+/// Renderer of the fonts built into OpenCV, which only cover ASCII.
+pub(crate) struct HersheyRenderer {
+    font_face: i32,
+    scale: f64,
+    thickness: i32,
+}
+
+impl HersheyRenderer {
+    /// Drops the degree sign, so "21.5 °C" is drawn as "21.5 C", and replaces the other
+    /// characters the Hershey fonts don't have.
+    fn printable_text(text: &str) -> String {
+        text.chars()
+            .filter(|c| *c != '°')
+            .map(|c| if c.is_ascii() { c } else { '?' })
+            .collect()
+    }
+}
+
+impl TextRenderer for HersheyRenderer {
+    fn measure(&self, text: &str) -> VideoResult<(Size, i32)> {
+        let mut baseline = 0;
+        let text_size = imgproc::get_text_size(
+            &Self::printable_text(text),
+            self.font_face,
+            self.scale,
+            self.thickness,
+            &mut baseline,
+        )?;
+        Ok((text_size, baseline))
+    }
+
+    fn draw(&self, image: &mut Mat, text: &str, origin: Point, color: Scalar) -> VideoResult<()> {
+        imgproc::put_text(
+            image,
+            &Self::printable_text(text),
+            origin,
+            self.font_face,
+            self.scale,
+            color,
+            self.thickness,
+            imgproc::LINE_8,
+            false,
+        )?;
+        Ok(())
+    }
+}
+
+/// TrueType or OpenType font loaded from a file, parsed once for shaping and drawing.
+pub(crate) struct TrueTypeFont {
+    face: rustybuzz::Face<'static>,
+    font: FontRef<'static>,
+    /// Bytes of the file borrowed by `face` and `font`, declared after them so it is
+    /// dropped last.
+    _data: Arc<[u8]>,
+}
+
+impl TrueTypeFont {
+    pub(crate) fn load(path: &Path) -> VideoResult<Self> {
+        let font_error =
+            |message: String| VideoStreamError::FontError(path.display().to_string(), message);
+
+        let data: Arc<[u8]> = std::fs::read(path)
+            .map_err(|e| font_error(e.to_string()))?
+            .into();
+        /*
+        SAFETY: the bytes are never changed and stay at the same address until `data`
+        is dropped, which happens after `face` and `font`, the only borrowers of the
+        'static slice, which are never handed out with that lifetime.
+        */
+        let bytes: &'static [u8] = unsafe { std::slice::from_raw_parts(data.as_ptr(), data.len()) };
+        let face = rustybuzz::Face::from_slice(bytes, 0)
+            .ok_or_else(|| font_error("not a TrueType or OpenType font".to_string()))?;
+        let font = FontRef::try_from_slice(bytes).map_err(|e| font_error(e.to_string()))?;
+        Ok(Self {
+            face,
+            font,
+            _data: data,
+        })
+    }
+}
+
+/// Renderer of font files, with Unicode shaping and anti-aliasing.
+pub(crate) struct TrueTypeRenderer {
+    font: TrueTypeFont,
+    scale: PxScale,
+}
+
+/// Glyph of shaped text, with its position relative to the origin of the text.
+struct PositionedGlyph {
+    id: GlyphId,
+    x: f32,
+    y: f32,
+}
+
+impl TrueTypeRenderer {
+    /// Shapes the text, which handles ligatures, combining accents and right to left
+    /// scripts. Returns the positioned glyphs and the width of the text.
+    fn shape(&self, text: &str) -> (Vec<PositionedGlyph>, f32) {
+        let scale_factor = self.font.font.as_scaled(self.scale).h_scale_factor();
+
+        let mut buffer = rustybuzz::UnicodeBuffer::new();
+        buffer.push_str(text);
+        buffer.guess_segment_properties();
+        let glyph_buffer = rustybuzz::shape(&self.font.face, &[], buffer);
+
+        let mut glyphs = Vec::with_capacity(glyph_buffer.len());
+        let mut x = 0.0;
+        for (info, position) in glyph_buffer
+            .glyph_infos()
+            .iter()
+            .zip(glyph_buffer.glyph_positions())
+        {
+            glyphs.push(PositionedGlyph {
+                id: GlyphId(info.glyph_id as u16),
+                x: x + position.x_offset as f32 * scale_factor,
+                y: -position.y_offset as f32 * scale_factor,
+            });
+            x += position.x_advance as f32 * scale_factor;
+        }
+
+        (glyphs, x)
+    }
+}
+
+impl TextRenderer for TrueTypeRenderer {
+    fn measure(&self, text: &str) -> VideoResult<(Size, i32)> {
+        let scaled_font = self.font.font.as_scaled(self.scale);
+        let (_, width) = self.shape(text);
+
+        let text_size = Size::new(width.ceil() as i32, scaled_font.ascent().ceil() as i32);
+        Ok((text_size, (-scaled_font.descent()).ceil() as i32))
+    }
+
+    fn draw(&self, image: &mut Mat, text: &str, origin: Point, color: Scalar) -> VideoResult<()> {
+        // Frames and slates are BGR, other images are left as they are
+        if image.typ() != CV_8UC3 {
+            return Ok(());
+        }
+        let image_size = image.size()?;
+        let (glyphs, _) = self.shape(text);
+
+        for glyph in glyphs {
+            let positioned = glyph.id.with_scale_and_position(
+                self.scale,
+                ab_glyph::point(origin.x as f32 + glyph.x, origin.y as f32 + glyph.y),
+            );
+            let Some(outline) = self.font.font.outline_glyph(positioned) else {
+                continue;
+            };
+            let bounds = outline.px_bounds();
+
+            outline.draw(|x, y, coverage| {
+                let column = bounds.min.x as i32 + x as i32;
+                let row = bounds.min.y as i32 + y as i32;
+                if column < 0 || row < 0 || column >= image_size.width || row >= image_size.height {
+                    return;
+                }
+                if let Ok(pixel) = image.at_2d_mut::<Vec3b>(row, column) {
+                    blend(pixel, &color, coverage);
+                }
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Mixes the colour into the pixel by the coverage of the glyph, which anti-aliases
+/// its edges.
+fn blend(pixel: &mut Vec3b, color: &Scalar, coverage: f32) {
+    let coverage = coverage.clamp(0.0, 1.0) as f64;
+    for channel in 0..3 {
+        let value = pixel[channel] as f64 * (1.0 - coverage) + color[channel] * coverage;
+        pixel[channel] = value.round() as u8;
+    }
+}
+
+fn font_face(font: OverlayFont) -> i32 {
+    match font {
+        OverlayFont::Simplex => imgproc::FONT_HERSHEY_SIMPLEX,
+        OverlayFont::Plain => imgproc::FONT_HERSHEY_PLAIN,
+        OverlayFont::Duplex => imgproc::FONT_HERSHEY_DUPLEX,
+        OverlayFont::Complex => imgproc::FONT_HERSHEY_COMPLEX,
+        OverlayFont::Triplex => imgproc::FONT_HERSHEY_TRIPLEX,
+        OverlayFont::ComplexSmall => imgproc::FONT_HERSHEY_COMPLEX_SMALL,
+        OverlayFont::ScriptSimplex => imgproc::FONT_HERSHEY_SCRIPT_SIMPLEX,
+        OverlayFont::ScriptComplex => imgproc::FONT_HERSHEY_SCRIPT_COMPLEX,
+    }
+}
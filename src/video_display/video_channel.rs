@@ -1,202 +1,240 @@
-use crate::video_display::VideoResult;
-use crate::video_display::{image_manipulation, ImageFrame};
-use opencv::core::{Mat, Point, Vector};
-use opencv::hub_prelude::VideoCaptureTrait;
-use opencv::imgproc;
-use opencv::videoio::{VideoCapture, CAP_PROP_BUFFERSIZE};
-use std::collections::VecDeque;
+use crate::config::{ChannelConfig, FrameAnnotation, RecordingConfig, SnapshotConfig};
+use crate::core::shutdown;
+use crate::video_display::capture_worker::ChannelShared;
+use crate::video_display::motion_detection::MotionState;
+use crate::video_display::recording::ChannelRecordingSettings;
+use crate::video_display::snapshot::ChannelSnapshotSettings;
+use crate::video_display::text_layout::OverlayText;
+use crate::video_display::text_renderer::TextRenderer;
+use crate::video_display::{capture_worker, image_manipulation, VideoResult, VideoStreamError};
+use opencv::core::{Mat, Size};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
 use std::time::{Duration, Instant};
 
-// TODO: does it make sense for these to be configurable?
-const IMG_DIFF_THRESHOLD: f64 = 10.0;
-const MIN_CONTOUR_AREA: f64 = 10000.0;
-
+#[derive(Clone, Copy)]
 pub struct VideoChannelSettings {
-    frame_duration: Duration,
-    mvn_update_interval: Duration,
-    mvn_comparison_interval: Duration,
+    pub(crate) frame_duration: Duration,
+    pub(crate) mvn_update_interval: Duration,
+    pub(crate) mvn_comparison_interval: Duration,
+    pub(crate) reconnect_initial_delay: Duration,
+    pub(crate) reconnect_max_delay: Duration,
+    pub(crate) max_reconnect_attempts: u32,
 }
 
 impl VideoChannelSettings {
-    pub(crate) fn new(
-        frame_duration: Duration,
-        mvn_check_interval: Duration,
-        mvn_comparison_interval: Duration,
-    ) -> Self {
+    pub(crate) fn from_config(config: &ChannelConfig) -> Self {
         Self {
-            frame_duration,
-            mvn_update_interval: mvn_check_interval,
-            mvn_comparison_interval,
+            frame_duration: config.frame_duration(),
+            mvn_update_interval: config.motion_check_interval(),
+            mvn_comparison_interval: config.motion_comparison_interval(),
+            reconnect_initial_delay: Duration::from_millis(config.reconnect_initial_delay_ms),
+            reconnect_max_delay: Duration::from_millis(config.reconnect_max_delay_ms),
+            max_reconnect_attempts: config.max_reconnect_attempts,
         }
     }
 
-    pub(crate) fn default() -> Self {
-        // 50 frames per second
-        let frame_duration = Duration::from_millis(1000 / 50);
-        Self::new(
-            frame_duration,
-            // Check for movement every 5 frames
-            frame_duration * 5,
-            // Use the image from 10 frames ago to compare against the current frame
-            frame_duration * 10,
-        )
+    pub(crate) fn get_frame_duration(&self) -> Duration {
+        self.frame_duration
     }
+}
 
-    pub(crate) fn get_frame_duration(&self) -> Duration {
-        self.frame_duration.clone()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelState {
+    Connected,
+    Reconnecting,
+    /// The stream could not be reopened after the maximum number of attempts. Retries
+    /// still happen at the maximum backoff delay.
+    Failed,
+}
+
+impl fmt::Display for ChannelState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChannelState::Connected => write!(f, "connected"),
+            ChannelState::Reconnecting => write!(f, "reconnecting"),
+            ChannelState::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// Connection details of a channel, updated by its capture thread.
+#[derive(Debug, Clone)]
+pub struct ChannelStatus {
+    pub state: ChannelState,
+    pub last_error: Option<String>,
+    pub offline_since: Option<Instant>,
+}
+
+impl Default for ChannelStatus {
+    fn default() -> Self {
+        Self {
+            state: ChannelState::Reconnecting,
+            last_error: None,
+            offline_since: None,
+        }
+    }
+}
+
+impl ChannelStatus {
+    pub(crate) fn set_state(&mut self, channel_name: &str, state: ChannelState) {
+        if self.state != state {
+            println!("Camera {} is {}", channel_name, state);
+            self.state = state;
+        }
     }
 }
 
 pub struct VideoChannel {
-    pub camera: VideoCapture,
-    pub settings: VideoChannelSettings,
-    frame_buffer: VecDeque<ImageFrame>,
-    contours: Vector<Vector<Point>>,
-    last_mvn_check: Instant,
+    pub name: String,
+    pub url: String,
+    shared: Arc<ChannelShared>,
+    is_running: Arc<AtomicBool>,
+    capture_handle: Option<JoinHandle<()>>,
 }
 
 impl VideoChannel {
-    pub(crate) fn new(mut camera: VideoCapture, settings: VideoChannelSettings) -> Self {
-        /*
-        Ensure the buffer is small enough that we are always reading the latest
-        image from the stream.
-        */
-        let _ = camera.set(CAP_PROP_BUFFERSIZE, 1.0);
+    /// Creates the channel and starts reading its stream in the background. A stream
+    /// that can't be opened doesn't fail the channel, it keeps being retried.
+    pub(crate) fn open(
+        config: &ChannelConfig,
+        recording: &RecordingConfig,
+        snapshots: &SnapshotConfig,
+    ) -> Self {
+        let shared = Arc::new(ChannelShared::new(
+            VideoChannelSettings::from_config(config),
+            ChannelRecordingSettings::from_config(recording, &config.name),
+            ChannelSnapshotSettings::from_config(snapshots, &config.name),
+        ));
+        let is_running = Arc::new(AtomicBool::new(true));
+        let capture_handle = capture_worker::start(
+            config.name.clone(),
+            config.url.clone(),
+            shared.clone(),
+            is_running.clone(),
+        );
 
         Self {
-            camera,
-            settings,
-            frame_buffer: VecDeque::new(),
-            contours: Vector::new(),
-            last_mvn_check: Instant::now(),
+            name: config.name.clone(),
+            url: config.url.clone(),
+            shared,
+            is_running,
+            capture_handle: Some(capture_handle),
         }
     }
 
-    fn get_background_image(&mut self) -> Option<ImageFrame> {
-        let background_instant = Instant::now() - self.settings.mvn_comparison_interval;
+    pub fn state(&self) -> ChannelState {
+        self.shared.status().state
+    }
 
-        while !self.frame_buffer.is_empty()
-            && self.frame_buffer.front().unwrap().instant < background_instant
-        {
-            let background_frame = self.frame_buffer.pop_front().unwrap();
-            let next_frame_instant = background_instant + self.settings.frame_duration;
+    /// Result of the latest movement check. Movement is checked on every channel,
+    /// whether it is displayed or not.
+    pub fn motion(&self) -> MotionState {
+        self.shared.motion()
+    }
 
-            if next_frame_instant > background_instant {
-                return Some(background_frame);
-            }
-        }
+    pub fn settings(&self) -> VideoChannelSettings {
+        self.shared.settings()
+    }
 
-        println!(
-            "No background image found. Frame buffer size={}",
-            self.frame_buffer.len()
-        );
-        None
+    pub(crate) fn update_settings(&mut self, settings: VideoChannelSettings) {
+        self.shared.set_settings(settings);
     }
 
-    pub(crate) fn create_frame_image(&mut self) -> VideoResult<Mat> {
-        let mut image = Mat::default();
-        self.camera.read(&mut image)?;
+    pub(crate) fn update_recording(&mut self, settings: ChannelRecordingSettings) {
+        self.shared.set_recording(settings);
+    }
 
-        let update_movement = self.last_mvn_check.elapsed() >= self.settings.mvn_update_interval;
-        if update_movement {
-            self.last_mvn_check = Instant::now();
-            let background = self.get_background_image();
+    pub(crate) fn update_snapshots(&mut self, settings: ChannelSnapshotSettings) {
+        self.shared.set_snapshots(settings);
+    }
 
-            self.frame_buffer.push_back(ImageFrame {
-                image: image_manipulation::to_gray_image(&image)?,
-                instant: Instant::now(),
-            });
+    /// Overlay text drawn over the recordings that are composited.
+    pub(crate) fn update_overlay_texts(&self, overlay_texts: Vec<OverlayText>) {
+        self.shared.set_overlay_texts(overlay_texts);
+    }
 
-            let current_frame = self.frame_buffer.back().unwrap();
-            if let Some(background_image) = background {
-                let img_diff = get_image_diff(&current_frame.image, &background_image.image)?;
-                self.contours = get_movement_contours(&img_diff)?;
-            } else {
-                println!("No background image found for {:?}", current_frame.instant);
+    /// Stops the capture thread, which releases the stream.
+    pub(crate) fn stop(&self) {
+        self.is_running.store(false, Ordering::Relaxed);
+    }
+
+    pub(crate) fn join(&mut self, deadline: Instant) -> bool {
+        match self.capture_handle.take() {
+            Some(handle) => {
+                shutdown::join_until(handle, deadline, &format!("camera {}", self.name))
             }
+            None => true,
         }
+    }
 
-        self.draw_contours(&mut image)?;
+    /// Creates the placeholder frame shown while the channel can't deliver images.
+    pub(crate) fn create_slate_image(
+        &self,
+        size: Size,
+        renderer: &dyn TextRenderer,
+    ) -> VideoResult<Mat> {
+        let status = self.shared.status().clone();
+        let mut lines = vec![
+            "No signal".to_string(),
+            self.name.clone(),
+            format!("Status: {}", status.state),
+        ];
+        if let Some(offline_since) = status.offline_since {
+            lines.push(format!(
+                "Offline for {}",
+                format_duration(offline_since.elapsed())
+            ));
+        }
+        if let Some(last_error) = &status.last_error {
+            lines.push(format!("Last error: {}", last_error));
+        }
 
-        Ok(image)
+        image_manipulation::create_slate(size, &lines, renderer)
     }
 
-    fn draw_contours(&mut self, image: &mut Mat) -> VideoResult<u32> {
-        let mut moving_parts = 0;
-        for contour in &self.contours {
-            let area = imgproc::contour_area(&contour, false)?;
-
-            if area < MIN_CONTOUR_AREA {
-                continue;
+    /// Takes the latest frame read by the capture thread, without waiting for the stream,
+    /// with the motion boxes when the annotation has them. The frames of the capture
+    /// thread are never drawn on, so they stay raw for the other outputs.
+    pub(crate) fn create_frame_image(&mut self, annotation: FrameAnnotation) -> VideoResult<Mat> {
+        let result = self.build_frame_image(annotation);
+        if let Err(e) = &result {
+            if !matches!(e, VideoStreamError::StreamUnavailable(_)) {
+                self.shared.status().last_error = Some(e.to_string());
             }
-            moving_parts += 1;
-
-            let contour_rect = imgproc::bounding_rect(&contour)?;
-
-            let color = opencv::core::Scalar {
-                0: [0.0, 0.0, 255.0, 0.0],
-            };
-            imgproc::rectangle(
-                image,
-                contour_rect,
-                color,
-                3,
-                imgproc::LineTypes::LINE_8 as i32,
-                0,
-            )?;
+        }
+        result
+    }
+
+    fn build_frame_image(&mut self, annotation: FrameAnnotation) -> VideoResult<Mat> {
+        let Some(frame) = self.shared.latest_frame() else {
+            return Err(VideoStreamError::StreamUnavailable(self.name.clone()));
+        };
+        let mut image = frame.image;
+
+        if annotation.draws_motion_boxes() {
+            self.motion().draw_regions(&mut image)?;
         }
 
-        Ok(moving_parts)
+        Ok(image)
     }
 }
 
-fn get_image_diff(image: &Mat, background_image: &Mat) -> VideoResult<Mat> {
-    // compare image with background
-    let mut diff = Mat::default();
-    opencv::core::absdiff(&image, &background_image, &mut diff)?;
-
-    // filter the diff img to get only differences larger than the defined threshold
-    let mut thresh_diff = Mat::default();
-    imgproc::threshold(
-        &diff,
-        &mut thresh_diff,
-        IMG_DIFF_THRESHOLD,
-        255.0,
-        imgproc::THRESH_BINARY,
-    )?;
-
-    Ok(thresh_diff)
+impl Drop for VideoChannel {
+    fn drop(&mut self) {
+        // Channels removed by a config reload stop their capture thread on their own
+        self.stop();
+    }
 }
 
-fn get_movement_contours(img_diff: &Mat) -> VideoResult<Vector<Vector<Point>>> {
-    let kernel = imgproc::get_structuring_element(
-        imgproc::MORPH_RECT,
-        opencv::core::Size {
-            width: 3,
-            height: 3,
-        },
-        Point { x: -1, y: -1 },
-    )?;
-
-    let mut dilated = Mat::default();
-
-    imgproc::dilate(
-        &img_diff,
-        &mut dilated,
-        &kernel,
-        Point { x: -1, y: -1 },
-        2,
-        opencv::core::BorderTypes::BORDER_CONSTANT as i32,
-        opencv::core::Scalar::default(),
-    )?;
-
-    let mut contours = Vector::<Vector<Point>>::new();
-    imgproc::find_contours_def(
-        &dilated,
-        &mut contours,
-        imgproc::RETR_EXTERNAL,
-        imgproc::CHAIN_APPROX_SIMPLE,
-    )?;
-
-    Ok(contours)
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
 }